@@ -0,0 +1,105 @@
+// Calls every function in rubyfmt.h the way a C program would. Build it
+// with `make target/c_abi_test`, which links a librubyfmt built with the
+// test_hooks feature so rubyfmt_test_panic_next_call is there.
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include "../librubyfmt/include/rubyfmt.h"
+
+#define CHECK(cond)                                                       \
+    do {                                                                  \
+        if (!(cond)) {                                                    \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,        \
+                    __LINE__, #cond);                                     \
+            exit(1);                                                      \
+        }                                                                 \
+    } while (0)
+
+static int starts_with(const char* s, const char* prefix) {
+    return s != NULL && strncmp(s, prefix, strlen(prefix)) == 0;
+}
+
+// formats buf, checking the call succeeded and the output is expected
+static void check_formats(const char* buf, const RubyfmtOptions* options, const char* expected) {
+    int64_t status = -1;
+    RubyfmtString* out = rubyfmt_format_buffer_with_options(
+        (const unsigned char*)buf, strlen(buf), options, &status);
+    CHECK(status == RUBYFMT_FORMAT_ERROR_OK);
+    CHECK(out != NULL);
+    CHECK(rubyfmt_last_error_message() == NULL);
+    CHECK(rubyfmt_string_len(out) == strlen(expected));
+    CHECK(memcmp(rubyfmt_string_ptr(out), expected, strlen(expected)) == 0);
+    rubyfmt_string_free(out);
+}
+
+// formats buf, checking the call failed with status
+static void check_fails(const char* buf, int64_t expected_status) {
+    int64_t status = -1;
+    RubyfmtString* out = rubyfmt_format_buffer(
+        (const unsigned char*)buf, strlen(buf), &status);
+    CHECK(out == NULL);
+    CHECK(status == expected_status);
+    CHECK(rubyfmt_last_error_message() != NULL);
+}
+
+int main() {
+    const char* version = rubyfmt_version();
+    CHECK(version != NULL && strlen(version) > 0);
+
+    CHECK(rubyfmt_init() == RUBYFMT_INIT_STATUS_OK);
+    CHECK(rubyfmt_init() == RUBYFMT_INIT_STATUS_OK);
+
+    RubyfmtOptions options = rubyfmt_default_options();
+    CHECK(options.line_width == 120);
+    CHECK(options.indent_width == 2);
+    check_formats("a 1,2\n", NULL, "a(1, 2)\n");
+    check_formats("a \"b\"\n", &options, "a(\"b\")\n");
+    options.prefer_single_quotes = true;
+    check_formats("a \"b\"\n", &options, "a('b')\n");
+
+    int64_t status = -1;
+    const char* formatted = "a(1, 2)\n";
+    CHECK(rubyfmt_is_formatted(
+        (const unsigned char*)formatted, strlen(formatted), NULL, &status));
+    CHECK(status == RUBYFMT_FORMAT_ERROR_OK);
+    const char* unformatted = "a 1,2\n";
+    CHECK(!rubyfmt_is_formatted(
+        (const unsigned char*)unformatted, strlen(unformatted), NULL, &status));
+    CHECK(status == RUBYFMT_FORMAT_ERROR_OK);
+
+    // syntax errors are reported with their line and column
+    check_fails("a(\n", RUBYFMT_FORMAT_ERROR_SYNTAX_ERROR);
+    int line = 0, column = -1;
+    CHECK(sscanf(rubyfmt_last_error_message(), "%d:%d:", &line, &column) == 2);
+    CHECK(line >= 1 && column >= 0);
+    const char* broken = "def\n";
+    CHECK(!rubyfmt_is_formatted(
+        (const unsigned char*)broken, strlen(broken), NULL, &status));
+    CHECK(status == RUBYFMT_FORMAT_ERROR_SYNTAX_ERROR);
+
+    options.line_width = 0;
+    CHECK(rubyfmt_format_buffer_with_options(
+        (const unsigned char*)formatted, strlen(formatted), &options, &status) == NULL);
+    CHECK(status == RUBYFMT_FORMAT_ERROR_INVALID_OPTIONS);
+    CHECK(starts_with(rubyfmt_last_error_message(), "invalid options:"));
+
+    // a panic while formatting fails that call, and leaves rubyfmt usable
+    rubyfmt_test_panic_next_call();
+    check_fails("a 1\n", RUBYFMT_FORMAT_ERROR_INTERNAL_PANIC);
+    CHECK(starts_with(rubyfmt_last_error_message(), "rubyfmt panicked:"));
+    check_formats("a 1\n", NULL, "a(1)\n");
+
+    // but one while starting the VM poisons it, so every later call fails
+    rubyfmt_test_panic_next_call();
+    CHECK(rubyfmt_init() == RUBYFMT_INIT_STATUS_ERROR);
+    CHECK(starts_with(rubyfmt_last_error_message(), "rubyfmt panicked:"));
+    CHECK(rubyfmt_init() == RUBYFMT_INIT_STATUS_ERROR);
+    check_fails("a 1\n", RUBYFMT_FORMAT_ERROR_INTERNAL_PANIC);
+    CHECK(strstr(rubyfmt_last_error_message(), "starting the Ruby VM") != NULL);
+    CHECK(!rubyfmt_is_formatted(
+        (const unsigned char*)formatted, strlen(formatted), NULL, &status));
+    CHECK(status == RUBYFMT_FORMAT_ERROR_INTERNAL_PANIC);
+
+    printf("C ABI ok\n");
+    return 0;
+}
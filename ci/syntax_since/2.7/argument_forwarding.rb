@@ -0,0 +1,3 @@
+def f(...)
+  g(...)
+end
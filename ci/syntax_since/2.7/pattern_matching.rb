@@ -0,0 +1,4 @@
+case [1]
+in [a]
+  a
+end
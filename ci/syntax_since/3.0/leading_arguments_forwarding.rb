@@ -0,0 +1,3 @@
+def f(a, ...)
+  g(a, ...)
+end
@@ -23,16 +23,11 @@ module MathsAndPhysics
     # Eligible JAC codes for this policy:
     # https://www.gov.uk/government/publications/additional-payments-for-teaching-eligibility-and-payment-details/claim-a-payment-for-teaching-maths-or-physics-eligibility-and-payment-details#teachers-qualifications
     ELIGIBLE_JAC_CODES = [
-      # Mathematics
-      "G1",
-      # Operational research
-      "G2",
-      # Statistics
-      "G3",
-      # Others in mathematical sciences
-      "G9",
-      # Physics
-      "F3"
+      "G1", # Mathematics
+      "G2", # Operational research
+      "G3", # Statistics
+      "G9", # Others in mathematical sciences
+      "F3" # Physics
     ].freeze
 
     # The record transformed from a DQTReportCsv. Expected to contain the keys:
@@ -0,0 +1,11 @@
+def foo(...)
+  bar(...)
+end
+
+def method_missing(...)
+  super(...)
+end
+
+def log(...)
+  raise(...)
+end
@@ -0,0 +1,6 @@
+result = items
+  # only the active ones
+  .select(&:active?)
+  # newest first
+  .sort_by(&:created_at)
+  .map(&:name) # names
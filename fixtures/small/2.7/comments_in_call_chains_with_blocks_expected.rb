@@ -0,0 +1,14 @@
+totals = orders
+  # skip the ones still in a cart
+  .reject(&:pending?)
+  # in cents
+  .map do |order|
+    # before any discounts
+    order.total
+  end
+  .sort
+
+orders
+  .each { |order| order.ship! }
+  # only the ones that made it
+  .select(&:shipped?)
@@ -0,0 +1,6 @@
+[1, 2, 3].map { _1 * 2 }
+{a: 1}.each { puts(_1, _2) }
+[[1, 2]].each do
+  puts(_1)
+  puts(_2)
+end
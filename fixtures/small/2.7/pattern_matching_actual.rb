@@ -0,0 +1,44 @@
+case config
+in {db: {user:}}
+  user
+in {name: String => name, roles: [:admin, *]} if name != "root"
+  name
+in [Integer => x, Integer | Float => y, *rest] unless rest.empty?
+  x+y
+in Point(x:, y: 0)
+  x
+in Point[x, y]
+  y
+in Point[x:, y: 1]
+  x
+in Point(x, y, *)
+  y
+in ^expected
+  expected
+in nil | false
+  nil
+in {status: "active", **nil}
+  :active
+in {}
+  :empty
+in []
+  :empty
+in {first_name: String => first_name, last_name: String => last_name, address: {city:, country: "US"}, phone: String => phone}
+  first_name
+else
+  raise
+end
+
+case [1, 2]
+in 0..5, x
+  x
+end
+
+config in {db: {user:}}
+
+case point
+in Point()
+  :no_fields
+in Point[]
+  :no_elements
+end
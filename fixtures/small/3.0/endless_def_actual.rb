@@ -0,0 +1,15 @@
+def square(x) = x*x
+def self.origin = Point.new(0, 0)
+def name() = "rubyfmt"
+
+class Point
+  def distance_to(other) = Math.sqrt((x - other.x) ** 2 + (y - other.y) ** 2)
+  def to_s = "(#{x}, #{y})"
+end
+
+def a_very_long_method_name_that_goes_on(and_takes, some_arguments) = do_the_thing_with(and_takes, some_arguments, and_some_more_arguments)
+
+# a regular def stays one, however short
+def double(x)
+  x * 2
+end
@@ -0,0 +1,10 @@
+compute_total => total
+config => {db: {user:, password:}}
+response => [Integer => status, *, String => body]
+
+case values
+in [*, 42 => found, *post]
+  found
+in Values[*pre, String, *]
+  pre
+end
@@ -0,0 +1,15 @@
+options={:verbose=>true,"name"=>"rubyfmt"}
+options => {verbose:}
+
+begin
+  load_config("path"=>path) => {db:}
+rescue KeyError => e
+  log(:error=>e) => {error:}
+end
+
+case response
+in {status: Integer => status}
+  wrap(:status=>status) => {body:}
+end
+
+lookup(:key=>1) in {key:}
@@ -0,0 +1,10 @@
+x = 1
+y = 2
+point = {x:, y:}
+build(x:, y:)
+Point.new(x:, y: y + 1)
+
+case point
+in {x: ^(x + 0), y: ^@y}
+  point
+end
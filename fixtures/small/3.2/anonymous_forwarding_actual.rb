@@ -0,0 +1,7 @@
+def rest(*) = other(*)
+def kwrest(**) = other(**)
+def block(&) = other(&)
+
+def all(*, **, &)
+  other(*, **, &)
+end
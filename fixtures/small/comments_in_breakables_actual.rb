@@ -0,0 +1,14 @@
+config = {
+  # the name
+  name: "rubyfmt",
+  # the version
+  version: 1,
+  # more to come
+}
+
+foo(
+  # first
+  a,
+  # second
+  b
+)
@@ -0,0 +1,20 @@
+=begin
+Copyright (c) the authors.
+  Licensed under the MIT license.
+=end
+class Foo
+  def bar
+=begin
+    old_implementation(  1 )
+=end
+    new_implementation(1)
+  end
+
+=begin
+  docs for baz
+=end
+  def baz
+    # a regular comment
+    1
+  end
+end
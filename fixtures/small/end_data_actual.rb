@@ -0,0 +1,10 @@
+DATA.each_line do |line|
+  puts(line.upcase)
+end
+# this comment comes before the data
+__END__
+  not   ruby,   so   left   alone
+def foo( x ) ; end
+# and neither is this comment
+
+trailing blank line above
@@ -0,0 +1,7 @@
+puts DATA.read.inspect
+__END__
+crlf line
+lf line
+lone crstill the same line
+
+no newline at the end
\ No newline at end of file
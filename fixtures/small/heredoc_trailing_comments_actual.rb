@@ -0,0 +1,9 @@
+a = <<EOD # the text
+part 1 of heredoc
+EOD
+
+foo = <<-EOM.gsub(".", "b") # swapped
+qoiefjqwoeifjqwe
+EOM
+
+puts a,foo # both
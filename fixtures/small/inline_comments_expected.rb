@@ -15,9 +15,7 @@ module MathsAndPhysics
   #   degree_codes:       The corresponding JAC codes to the subject(s)
   #                       the teacher completed their degree in.
   CONST = [
-    #maths
-    a,
-    #physics
-    b
+    a, #maths
+    b #physics
   ]
 end
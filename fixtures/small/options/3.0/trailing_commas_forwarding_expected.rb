@@ -0,0 +1,8 @@
+def forward(...)
+  target(
+    first_argument_with_a_long_enough_name,
+    second_argument_with_a_long_enough_name,
+    third_argument_with_a_long_name,
+    ...
+  )
+end
@@ -0,0 +1,5 @@
+a = 1 # one
+bcd = 2 # two
+ef = 3 # three
+
+x = 10 # a run of one is left alone
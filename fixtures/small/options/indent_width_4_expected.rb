@@ -0,0 +1,10 @@
+class Foo
+    def bar
+        baz(1)
+    end
+end
+
+ITEMS = [
+    1,
+    2
+]
@@ -0,0 +1,6 @@
+a = 1
+
+b = 2
+
+
+c = 3
@@ -0,0 +1,5 @@
+a = "plain"
+b = "it's"
+c = "tab\t"
+d = "#{a} interpolated"
+e = 'already single'
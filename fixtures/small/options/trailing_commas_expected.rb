@@ -0,0 +1,32 @@
+numbers = [
+  1,
+  2,
+]
+
+config = {
+  name: "rubyfmt",
+  version: 1,
+}
+
+call(
+  first,
+  second,
+)
+
+call(
+  first,
+  &blk
+)
+
+call(
+  first,
+  key: 1
+)
+
+call(
+  first,
+  **opts
+)
+
+short = [1, 2]
+call(first, second)
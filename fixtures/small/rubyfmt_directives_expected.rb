@@ -0,0 +1,33 @@
+a(1, 2)
+
+# rubyfmt:disable
+MATRIX = [
+  [1, 0, 0],
+  [0, 1, 0],
+  [0, 0, 1],
+]
+
+WIDTHS = { small:  1,
+           medium: 10,
+           large:  100 }  # px
+# rubyfmt:enable
+
+b(3, 4)
+
+class Foo
+  # rubyfmt:skip
+  ROUTES = { "/"      => :index,
+             "/about" => :about }
+
+  def bar(x, y)
+    x + y
+  end
+
+  def baz
+    # rubyfmt:skip
+    call_it(1,  2)
+  end
+
+  # rubyfmt:skip
+  ITEMS.each { |i| puts(i,1) }
+end
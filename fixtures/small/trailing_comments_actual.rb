@@ -0,0 +1,14 @@
+foo(1) # explain
+x = 1 + # continued
+  2
+
+class Foo # the class
+  def bar(a, b) # the method
+    a + b # sum
+  end # bar
+end # Foo
+
+LIMITS = [
+  10, # soft
+  20 # hard
+]
@@ -0,0 +1,13 @@
+foo(1) # explain
+x = 1 + 2 # continued
+
+class Foo # the class
+  def bar(a, b) # the method
+    a + b # sum
+  end # bar
+end # Foo
+
+LIMITS = [
+  10, # soft
+  20 # hard
+]
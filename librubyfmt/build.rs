@@ -1,14 +1,44 @@
+use std::env;
 use std::io::{self, Write};
+#[cfg(feature = "ffi")]
+use std::path::PathBuf;
 use std::process::Command;
 
+// The ruby versions we can embed the grammar of, by the cargo feature that
+// picks them. Each needs its checkout in ruby_checkout/
+const RUBY_VERSIONS: &[(&str, &str)] = &[
+    ("CARGO_FEATURE_RUBY_2_6", "2.6.6"),
+    ("CARGO_FEATURE_RUBY_2_7", "2.7.8"),
+    ("CARGO_FEATURE_RUBY_3_2", "3.2.2"),
+];
+const DEFAULT_RUBY_VERSION: &str = "2.6.6";
+
 fn main() {
+    #[cfg(feature = "ffi")]
+    generate_header();
+
+    if env::var_os("CARGO_FEATURE_RUBY_EXTENSION").is_some() {
+        build_ruby_extension();
+        return;
+    }
+
+    let ruby_version = selected_ruby_version();
+    // e.g. 2.6 for 2.6.6, which is how ruby names its libraries
+    let short_version = &ruby_version[..ruby_version.rfind('.').expect("has a patch version")];
+    println!("cargo:rustc-env=RUBYFMT_RUBY_VERSION={}", ruby_version);
+    println!(
+        "cargo:rustc-env=RUBYFMT_RUBY_CHECKOUT=ruby-{}",
+        ruby_version
+    );
+
     #[cfg(target_os = "linux")]
-    let libname = "ruby-static";
+    let libname = "ruby-static".to_string();
     #[cfg(target_os = "macos")]
-    let libname = "ruby.2.6-static";
+    let libname = format!("ruby.{}-static", short_version);
+    let ripper_libname = format!("ripper.{}-static", short_version);
 
     let path = std::env::current_dir().expect("is current");
-    let ruby_checkout_path = path.join("ruby_checkout/ruby-2.6.6/");
+    let ruby_checkout_path = path.join(format!("ruby_checkout/ruby-{}/", ruby_version));
     if !ruby_checkout_path
         .join(format!("lib{}.a", libname))
         .exists()
@@ -16,7 +46,7 @@ fn main() {
         let o = Command::new("bash")
             .arg("-c")
             .arg(format!(
-                "autoconf && {}/configure --without-gmp --disable-jit-support && make -j",
+                "autoconf && {}/configure --without-gmp --disable-jit-support --disable-yjit && make -j",
                 ruby_checkout_path.display()
             ))
             .current_dir(&ruby_checkout_path)
@@ -28,10 +58,16 @@ fn main() {
             panic!("failed subcommand");
         }
     }
-    if !ruby_checkout_path.join("libripper.2.6-static.a").exists() {
+    if !ruby_checkout_path
+        .join(format!("lib{}.a", ripper_libname))
+        .exists()
+    {
         let o = Command::new("bash")
             .arg("-c")
-            .arg("ar crus libripper.2.6-static.a ext/ripper/ripper.o")
+            .arg(format!(
+                "ar crus lib{}.a ext/ripper/ripper.o",
+                ripper_libname
+            ))
             .current_dir(&ruby_checkout_path)
             .output()
             .expect("works");
@@ -58,13 +94,79 @@ fn main() {
         .compile("librubyfmt_c");
 
     println!(
-        "cargo:rustc-link-search=native={}/ruby_checkout/ruby-2.6.6",
-        path.display()
+        "cargo:rustc-link-search=native={}",
+        ruby_checkout_path.display()
     );
     println!("cargo:rustc-link-lib=static={}", libname);
-    println!("cargo:rustc-link-lib=static=ripper.2.6-static");
+    println!("cargo:rustc-link-lib=static={}", ripper_libname);
     println!("cargo:rustc-link-lib=dylib=z");
 
     #[cfg(target_os = "linux")]
     println!("cargo:rustc-link-lib=dylib=crypt");
 }
+
+fn selected_ruby_version() -> &'static str {
+    let selected: Vec<&str> = RUBY_VERSIONS
+        .iter()
+        .filter(|(feature, _)| env::var_os(feature).is_some())
+        .map(|(_, version)| *version)
+        .collect();
+    match selected[..] {
+        [] => DEFAULT_RUBY_VERSION,
+        [version] => version,
+        _ => panic!(
+            "only one ruby grammar can be embedded, but features for {} are all enabled",
+            selected.join(", ")
+        ),
+    }
+}
+
+// As a ruby extension we're loaded in to a ruby that's already running, so
+// we compile against its headers and leave every ruby symbol to be resolved
+// from it at load time, rather than building and linking our own ruby.
+fn build_ruby_extension() {
+    let rbconfig = |key: &str| {
+        let o = Command::new("ruby")
+            .arg("-rrbconfig")
+            .arg("-e")
+            .arg(format!("print RbConfig::CONFIG[{:?}]", key))
+            .output()
+            .expect("running ruby to find its headers");
+        if !o.status.success() {
+            io::stderr().write_all(&o.stderr).unwrap();
+            panic!("failed subcommand");
+        }
+        String::from_utf8(o.stdout).expect("ruby printed a utf-8 path")
+    };
+
+    cc::Build::new()
+        .file("src/rubyfmt.c")
+        .include(rbconfig("rubyhdrdir"))
+        .include(rbconfig("rubyarchhdrdir"))
+        .compile("librubyfmt_c");
+
+    // we parse with the host ruby's ripper, so it's that ruby's grammar
+    println!(
+        "cargo:rustc-env=RUBYFMT_RUBY_VERSION={}",
+        rbconfig("RUBY_PROGRAM_VERSION")
+    );
+
+    #[cfg(target_os = "macos")]
+    println!("cargo:rustc-cdylib-link-arg=-Wl,-undefined,dynamic_lookup");
+}
+
+// rubyfmt.h is generated from the C ABI in src/ffi.rs, so the two can't
+// drift apart. It only goes in OUT_DIR, so building never writes to the
+// source tree. `make header` copies it over the checked in
+// include/rubyfmt.h, which C users can use without running a build.
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("cargo sets OUT_DIR"));
+    let config = cbindgen::Config::from_file("cbindgen.toml").expect("reading cbindgen.toml");
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("src/ffi.rs")
+        .generate()
+        .expect("generating rubyfmt.h")
+        .write_to_file(out_dir.join("rubyfmt.h"));
+}
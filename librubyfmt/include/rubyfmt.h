@@ -1,49 +1,138 @@
 #ifndef RUBYFMT_H
 #define RUBYFMT_H
 
-int RUBYFMT_INIT_STATUS_OK = 0;
-int RUBYFMT_INIT_STATUS_ERROR = 1;
+// This file is generated by cbindgen from librubyfmt/src/ffi.rs, don't edit it by hand.
 
-enum Rubyfmt_FormatError {
-    RUBYFMT_FORMAT_ERROR_OK = 0,
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
 
-    // passed buffer contained a ruby syntax error. Non fatal, user should feel
-    // free to continue to call rubyfmt with non-error strings.
-    RUBYFMT_FORMAT_ERROR_SYNTAX_ERROR = 1,
+// kept for programs written against older versions of this header
+#define RUBYFMT_OTHER_RUBY_ERROR RUBYFMT_FORMAT_ERROR_OTHER_RUBY_ERROR
 
-    // this error is fatal, the calling program should not continue to execute
-    // rubyfmt and you should report a bug with the file that crashed rubyfmt
-    RUBYFMT_FORMAT_ERROR_RIPPER_PARSE_FAILURE = 2,
+typedef enum Rubyfmt_FormatError {
+  RUBYFMT_FORMAT_ERROR_OK = 0,
+  // passed buffer contained a ruby syntax error. Non fatal, user should feel
+  // free to continue to call rubyfmt with non-error strings.
+  RUBYFMT_FORMAT_ERROR_SYNTAX_ERROR = 1,
+  // this error is fatal, the calling program should not continue to execute
+  // rubyfmt and you should report a bug with the file that crashed rubyfmt
+  RUBYFMT_FORMAT_ERROR_RIPPER_PARSE_FAILURE = 2,
+  // an error occured during IO within the function, should be impossible
+  // and most likely indicates a programming error within rubyfmt, please
+  // file a bug
+  RUBYFMT_FORMAT_ERROR_IO_ERROR = 3,
+  // some unknown ruby error occured during execution fo Rubyfmt. This indicates
+  // a programming error. Please file a bug report and terminate the process
+  // and restart.
+  RUBYFMT_FORMAT_ERROR_OTHER_RUBY_ERROR = 4,
+  // the passed buffer wasn't valid utf-8. Non fatal, nothing was formatted.
+  RUBYFMT_FORMAT_ERROR_INVALID_UTF8 = 5,
+  // the passed buffer declared an encoding in its magic comment that ruby
+  // doesn't know, or contained bytes that aren't valid in that encoding.
+  // Non fatal, nothing was formatted.
+  RUBYFMT_FORMAT_ERROR_INVALID_ENCODING = 6,
+  // rubyfmt panicked. This is a bug, please report it along with the
+  // message from `rubyfmt_last_error_message`. A panic while formatting
+  // leaves rubyfmt usable, but one while starting the Ruby VM means every
+  // later call fails with this error too.
+  RUBYFMT_FORMAT_ERROR_INTERNAL_PANIC = 7,
+  // the Ruby VM couldn't be started, or was started on another thread and
+  // can only be used from that one. Nothing was formatted.
+  RUBYFMT_FORMAT_ERROR_INIT_FAILED = 8,
+  // the passed `RubyfmtOptions` can't be formatted with, like a zero
+  // `line_width` or `indent_width`. Nothing was formatted.
+  RUBYFMT_FORMAT_ERROR_INVALID_OPTIONS = 9,
+} Rubyfmt_FormatError;
 
-    // an error occured during IO within the function, should be impossible
-    // and most likely indicates a programming error within rubyfmt, please
-    // file a bug
-    RUBYFMT_FORMAT_ERROR_IO_ERROR = 3,
+typedef enum Rubyfmt_InitStatus {
+  RUBYFMT_INIT_STATUS_OK = 0,
+  RUBYFMT_INIT_STATUS_ERROR = 1,
+} Rubyfmt_InitStatus;
 
-    // some unknown ruby error occured during execution fo Rubyfmt. This indicates
-    // a programming error. Please file a bug report and terminate the process
-    // and restart.
-    RUBYFMT_OTHER_RUBY_ERROR = 4,
-};
+typedef struct RubyfmtString RubyfmtString;
 
-typedef struct _RubyfmtString RubyfmtString;
+// Options for `rubyfmt_format_buffer_with_options`. Start from
+// `rubyfmt_default_options()` rather than zero initialising this, so new
+// options keep their defaults.
+typedef struct RubyfmtOptions {
+  uintptr_t line_width;
+  uint32_t indent_width;
+  // use single quotes for strings without escapes or interpolation
+  bool prefer_single_quotes;
+  // add trailing commas to collections broken over multiple lines
+  bool trailing_commas;
+  // drop the blank lines the user wrote between statements
+  bool remove_blank_lines;
+  // 0 keeps the input's line endings, 1 forces LF and 2 forces CRLF
+  uint32_t line_endings;
+  // line up the trailing comments on consecutive lines in to one column
+  bool align_trailing_comments;
+} RubyfmtOptions;
 
-// setup rubyfmt, call once per process. Will return non zero (RUBYFMT_INIT_STATUS_ERROR)
-// if initialization failed
-int rubyfmt_init();
+#if defined(RUBYFMT_TEST_HOOKS)
+// Makes the next call that uses the Ruby VM panic, which is how the tests
+// reach `RUBYFMT_FORMAT_ERROR_INTERNAL_PANIC`. Making `rubyfmt_init` panic
+// this way poisons the VM for the rest of the process. Only exported by
+// builds with the `test_hooks` feature.
+void rubyfmt_test_panic_next_call(void);
+#endif
 
-// ask rubyfmt to format the passed buffer. Must be utf-8 encoded, and len
-// bytes long. Returns NULL and populates the err pointer with non zero if
-// an error occurs
-RubyfmtString *rubyfmt_format_buffer(unsigned char* buf, size_t len, enum Rubyfmt_FormatError* err);
+// Initialises the Ruby VM. Only the first call does anything, later calls
+// report how that first one went.
+int rubyfmt_init(void);
 
-// free a RubyfmtString after use
-void rubyfmt_string_free(RubyfmtString*);
+// # Safety
+// this function will fail, very badly, if len specifies more bytes than is
+// available in the passed buffer pointer. Data that isn't utf8, or in the
+// encoding its magic comment declares, is reported as
+// `FormatError::InvalidUtf8` or `FormatError::InvalidEncoding`. The output
+// is in the same encoding as the input.
+// Please don't pass too small buffers.
+struct RubyfmtString *rubyfmt_format_buffer(const uint8_t *ptr, uintptr_t len, int64_t *err);
 
-// Get a byte pointer from a RubyfmtString, is not a null terminated string,
-// but instead should be used with rubyfmt_string_len to get the number
-// of bytes in the string
-unsigned char* rubyfmt_string_ptr(const RubyfmtString*);
-size_t rubyfmt_string_len(const RubyfmtString*);
+// Like `rubyfmt_format_buffer`, formatting with the passed options. A null
+// options pointer formats with the defaults.
+//
+// # Safety
+// the same rules as `rubyfmt_format_buffer` apply, and options must either
+// be null or point to a valid `RubyfmtOptions`
+struct RubyfmtString *rubyfmt_format_buffer_with_options(const uint8_t *ptr,
+                                                         uintptr_t len,
+                                                         const struct RubyfmtOptions *options,
+                                                         int64_t *err);
 
-#endif
+// Returns true if formatting the passed buffer wouldn't change it, without
+// allocating a `RubyfmtString`. Returns false and populates err if the
+// buffer couldn't be formatted.
+//
+// # Safety
+// the same rules as `rubyfmt_format_buffer_with_options` apply
+bool rubyfmt_is_formatted(const uint8_t *ptr,
+                          uintptr_t len,
+                          const struct RubyfmtOptions *options,
+                          int64_t *err);
+
+// The options `rubyfmt_format_buffer` formats with
+struct RubyfmtOptions rubyfmt_default_options(void);
+
+// A description of the last error a rubyfmt function returned on this
+// thread, or null if the last call succeeded. Syntax errors are reported as
+// `line:column: message`, one per line, and panics as `rubyfmt panicked:`
+// followed by the panic message. The string is owned by rubyfmt, and is
+// valid until the next rubyfmt call on this thread.
+const char *rubyfmt_last_error_message(void);
+
+// The version of rubyfmt, as a null terminated string
+const char *rubyfmt_version(void);
+
+const uint8_t *rubyfmt_string_ptr(const struct RubyfmtString *s);
+
+uintptr_t rubyfmt_string_len(const struct RubyfmtString *s);
+
+// # Safety
+// rubyfmt_string must have come from `rubyfmt_format_buffer`, and must not
+// be used after it's been freed
+void rubyfmt_string_free(struct RubyfmtString *rubyfmt_string);
+
+#endif /* RUBYFMT_H */
@@ -1,4 +1,9 @@
-class Parser < Ripper::SexpBuilderPP
+# Everything is defined under Rubyfmt, so that when we're loaded as an
+# extension we don't clash with a `Parser` the host program already has
+module Rubyfmt
+end
+
+class Rubyfmt::Parser < Ripper::SexpBuilderPP
   ARRAY_SYMBOLS = {qsymbols: "%i", qwords: "%w", symbols: "%I", words: "%W"}.freeze
 
   def initialize(buf)
@@ -16,6 +21,7 @@ class Parser < Ripper::SexpBuilderPP
   def initialize(file_data)
     STDOUT.flush
     super(file_data)
+    @file_data = file_data
     @file_lines = file_data.split("\n")
 
     # heredoc stack is the stack of identified heredocs
@@ -33,6 +39,7 @@ class Parser < Ripper::SexpBuilderPP
       "return" => [],
       "when" => [],
       "case" => [],
+      "in" => [],
       "yield" => [],
       "break" => [],
       "super" => [],
@@ -40,14 +47,28 @@ class Parser < Ripper::SexpBuilderPP
     @tlambda_stack = []
     @array_location_stacks = []
     @lbrace_stack = []
+    # every `=>` we've seen that the node it belongs to hasn't claimed yet.
+    # Hash pairs, rescues and pattern bindings each take theirs, so what's
+    # left on top while a pattern is parsed is the `=>` of a rightward
+    # assignment
+    @rocket_stack = []
+    # the `":` of `"key": value`, which ripper reports the same as a
+    # `:"key" => value` pair
+    @label_end_stack = []
     @comments = {}
+    # the line an `=begin` we're inside of started on, and its lines so far
+    @embdoc_start = nil
+    @embdoc_lines = []
+    # the line of `__END__`, after which is data that ripper doesn't parse
+    @end_line = nil
+    @syntax_errors = []
   end
 
   def parse
     res = super
 
     if res != nil
-      [res, @comments]
+      [res, @comments, @end_line]
     else
       nil
     end
@@ -55,8 +76,63 @@ class Parser < Ripper::SexpBuilderPP
 
   attr_reader :comments_delete
 
+  # every error ripper reported while parsing, as [lineno, column, message]
+  attr_reader :syntax_errors
+
+  # The first and last line of every statement a `# rubyfmt:` directive
+  # could protect, as {first_line => last_line}. Ripper doesn't tell us where
+  # nodes end, so this reparses with RubyVM::AbstractSyntaxTree, which only
+  # happens when the file has a directive in it.
+  def statement_spans
+    return {} unless @comments.values.any? { |(comment, _, _)| comment.include?("rubyfmt:") }
+
+    spans = {}
+    collect_statement_spans(RubyVM::AbstractSyntaxTree.parse(@file_data), spans)
+    # a heredoc's body comes after the line its node ends on
+    @comments_delete.each do |heredoc_start, heredoc_end|
+      spans.each do |first, last|
+        if first <= heredoc_start && heredoc_start <= last && last < heredoc_end
+          spans[first] = heredoc_end
+        end
+      end
+    end
+    spans
+  end
+
   private
 
+  # nodes that hold statements, rather than being one
+  STATEMENT_CONTAINERS = [:SCOPE, :BLOCK, :RESCUE, :ENSURE].freeze
+
+  # the children of each node that are statements, when there's only one
+  # of them and so no BLOCK around it, like the body of a one line `def`
+  STATEMENT_BODIES = {
+    SCOPE: [2],
+    IF: [1, 2],
+    UNLESS: [1, 2],
+    WHILE: [1],
+    UNTIL: [1],
+    WHEN: [1],
+    IN: [1],
+    BEGIN: [0],
+    RESCUE: [0, 2],
+    RESBODY: [1],
+    ENSURE: [0, 1],
+  }.freeze
+
+  def collect_statement_spans(node, spans, statement = true)
+    return unless node.is_a?(RubyVM::AbstractSyntaxTree::Node)
+
+    if statement && !STATEMENT_CONTAINERS.include?(node.type)
+      first = node.first_lineno
+      spans[first] = [spans.fetch(first, first), node.last_lineno].max
+    end
+    bodies = STATEMENT_BODIES.fetch(node.type, [])
+    node.children.each_with_index do |child, idx|
+      collect_statement_spans(child, spans, node.type == :BLOCK || bodies.include?(idx))
+    end
+  end
+
   DELIM_CLOSE_PAREN={ '{' => '}', '[' => ']', '(' => ')', '<' => '>' }
 
   def escape_percent_array_paren_content(part, pattern)
@@ -153,8 +229,121 @@ class Parser < Ripper::SexpBuilderPP
     [:when, cond, body, tail, @kw_stacks["when"].pop]
   end
 
+  def on_op(op)
+    @rocket_stack << [lineno, column] if op == "=>"
+    super
+  end
+
+  def on_label_end(*args)
+    @label_end_stack << [lineno, column]
+    super
+  end
+
+  def on_assoc_new(key, value)
+    if key[0] == :dyna_symbol
+      # whichever came last of an unclaimed `":` and `=>` is this pair's, as
+      # any pair nested in the value has already claimed its own
+      label_end = @label_end_stack.last
+      rocket = @rocket_stack.last
+      if label_end && (rocket.nil? || (label_end <=> rocket) > 0)
+        @label_end_stack.pop
+      else
+        @rocket_stack.pop
+      end
+    elsif key[0] != :@label
+      @rocket_stack.pop
+    end
+    super
+  end
+
+  def on_rescue(exceptions, var, body, tail)
+    # `rescue Error => e`
+    @rocket_stack.pop unless var.nil?
+    super
+  end
+
+  def on_in(pattern, body, tail)
+    if body.nil? && rightward_assignment?
+      return [:rightward_assign, pattern, @rocket_stack.pop]
+    end
+    [:in, pattern, body, tail, @kw_stacks["in"].pop]
+  end
+
   def on_case(cond, body)
-    [:case, cond, body, @kw_stacks["case"].pop]
+    if body[0] == :rightward_assign
+      # `expr => pattern` comes to us as a case with a single bodyless in
+      [:rightward_assign, cond, body[1], body[2]]
+    elsif body[0] == :in && body[2].nil?
+      # as does `expr in pattern`, neither has a case keyword
+      [:case, cond, body, body[4]]
+    else
+      [:case, cond, body, @kw_stacks["case"].pop]
+    end
+  end
+
+  def on_binary(left, op, right)
+    # `pattern => name` inside a pattern
+    @rocket_stack.pop if op == :"=>"
+    super
+  end
+
+  def on_for(*args)
+    @kw_stacks["in"].pop
+    super
+  end
+
+  def on_aryptn(constant, *rest)
+    super << pattern_delimiter(constant, @array_location_stacks)
+  end
+
+  def on_fndptn(constant, *rest)
+    super << pattern_delimiter(constant, @array_location_stacks)
+  end
+
+  def on_hshptn(constant, *rest)
+    delimiter = pattern_delimiter(constant, @lbrace_stack)
+    # `in {"key": value}` has no pair to claim these
+    pattern_start = current_pattern_start
+    while pattern_start && (label_end = @label_end_stack.last) && (label_end <=> pattern_start) > 0
+      @label_end_stack.pop
+    end
+    super << delimiter
+  end
+
+  # The brackets and braces of a pattern never reach on_array or on_hash, so
+  # drop the location we pushed for this one's here to keep the stacks in
+  # step. Returns the delimiter after the constant of `Const(...)` or
+  # `Const[...]`, which mean the same, so that we keep the one written.
+  def pattern_delimiter(constant, stack)
+    if constant
+      const = constant.last
+      line, column = const[2]
+      delimiter = @file_lines[line - 1].byteslice(column + const[1].bytesize..-1)[/\S/]
+      # we only push a location for a bracket, not a paren
+      return delimiter unless delimiter == "["
+      stack = @array_location_stacks
+    end
+
+    pattern_start = current_pattern_start
+    if pattern_start && stack.last && (stack.last <=> pattern_start) > 0
+      stack.pop
+    end
+    delimiter
+  end
+
+  # where the pattern being parsed starts, after either its in keyword or
+  # the `=>` of a rightward assignment
+  def current_pattern_start
+    [@kw_stacks["in"].last, @rocket_stack.last].compact.max
+  end
+
+  # a bodyless in is rightward assignment when the last unclaimed `=>` comes
+  # after the last `in` keyword, as every other `=>` has been claimed by the
+  # pair, rescue or pattern binding it belongs to
+  def rightward_assignment?
+    rocket = @rocket_stack.last
+    in_location = @kw_stacks["in"].last
+    !rocket.nil? && (in_location.nil? || (rocket <=> in_location) > 0)
   end
 
   def on_yield(arg)
@@ -261,9 +450,50 @@ class Parser < Ripper::SexpBuilderPP
     super(*args)
   end
 
+  # comments are recorded as [comment, column, own_line], where own_line is
+  # false for comments that come after some code on the same line
   def on_comment(comment)
-    @comments[lineno] = comment
+    own_line = @file_lines[lineno - 1].byteslice(0, column).strip.empty?
+    @comments[lineno] = [comment, column, own_line]
+  end
+
+  # `=begin`/`=end` blocks are recorded as a single comment, keyed by the
+  # `=begin` line, so that they're emitted together at column 0
+  def on_embdoc_beg(embdoc)
+    @embdoc_start = lineno
+    @embdoc_lines = [embdoc]
+    super
+  end
+
+  def on_embdoc(embdoc)
+    @embdoc_lines << embdoc
+    super
+  end
+
+  def on_embdoc_end(embdoc)
+    @embdoc_lines << embdoc
+    @comments[@embdoc_start] = [@embdoc_lines.join, 0, true]
+    @embdoc_start = nil
+    @embdoc_lines = []
+    super
+  end
+
+  def on___end__(marker)
+    @end_line = lineno
+    super
+  end
+
+  def on_parse_error(message)
+    @syntax_errors << [lineno, column, message]
+  end
+
+  def compile_error(message)
+    @syntax_errors << [lineno, column, message]
   end
 end
 
-GC.disable
+# We hold on to ruby objects from rust, where the GC can't see them. When we
+# own the VM it's simplest to never collect. As an extension we're in the
+# host's VM, so Rubyfmt.format and Rubyfmt.formatted? hold the GC off for
+# just the length of each call instead.
+GC.disable unless Rubyfmt.respond_to?(:format)
@@ -3,7 +3,7 @@ use std::ops::Range;
 use crate::line_tokens::LineToken;
 use crate::types::{ColNumber, LineNumber};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CommentBlock {
     span: Range<LineNumber>,
     comments: Vec<String>,
@@ -19,7 +19,7 @@ impl CommentBlock {
     }
 
     pub fn add_line(&mut self, line: String) {
-        self.span.end += 1;
+        self.span.end += line_count(&line);
         self.comments.push(line);
     }
 
@@ -32,20 +32,40 @@ impl CommentBlock {
 
     pub fn apply_spaces(mut self, indent_depth: ColNumber) -> Self {
         for comment in &mut self.comments {
-            *comment = str::repeat(" ", indent_depth as _) + comment;
+            // ruby only recognises `=begin` and `=end` at the start of a line
+            if !is_embdoc(comment) {
+                *comment = str::repeat(" ", indent_depth as _) + comment;
+            }
         }
         self
     }
 
+    pub fn comments(&self) -> impl Iterator<Item = &str> {
+        self.comments.iter().map(|c| c.as_str())
+    }
+
     pub fn has_comments(&self) -> bool {
         !self.comments.is_empty()
     }
 
+    /// The number of source lines these comments cover, which is more than
+    /// the number of comments if any of them are `=begin`/`=end` blocks
     pub fn len(&self) -> usize {
-        self.comments.len()
+        self.comments.iter().map(|c| line_count(c) as usize).sum()
     }
 }
 
+/// Whether `comment` is an `=begin`/`=end` embedded document, rather than a
+/// `#` comment
+fn is_embdoc(comment: &str) -> bool {
+    comment.starts_with("=begin")
+}
+
+/// The number of source lines `comment` was written over
+pub fn line_count(comment: &str) -> LineNumber {
+    comment.lines().count().max(1) as LineNumber
+}
+
 pub trait Merge<Other = Self> {
     fn merge(&mut self, other: Other);
 }
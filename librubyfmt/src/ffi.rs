@@ -0,0 +1,414 @@
+use crate::file_io;
+use crate::format_options::{
+    BlankLinePolicy, CommentAlignment, FormatOptions, LineEndings, QuoteStyle, TrailingCommas,
+};
+use crate::formatter;
+use crate::{panic_message, RichFormatError};
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub struct RubyfmtString(Box<[u8]>);
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub enum InitStatus {
+    OK = 0,
+    ERROR = 1,
+}
+
+impl RichFormatError {
+    fn into_format_error(self) -> FormatError {
+        match self {
+            RichFormatError::SyntaxError(_) => FormatError::SyntaxError,
+            RichFormatError::RipperParseFailure(_) => FormatError::RipperParseFailure,
+            RichFormatError::IOError(_) => FormatError::IOError,
+            RichFormatError::OtherRubyError(_) => FormatError::OtherRubyError,
+            RichFormatError::InvalidUtf8(_) => FormatError::InvalidUtf8,
+            RichFormatError::InvalidEncoding(_) => FormatError::InvalidEncoding,
+            RichFormatError::InitFailed(_) => FormatError::InitFailed,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub enum FormatError {
+    OK = 0,
+    /// passed buffer contained a ruby syntax error. Non fatal, user should feel
+    /// free to continue to call rubyfmt with non-error strings.
+    SyntaxError = 1,
+    /// this error is fatal, the calling program should not continue to execute
+    /// rubyfmt and you should report a bug with the file that crashed rubyfmt
+    RipperParseFailure = 2,
+    /// an error occured during IO within the function, should be impossible
+    /// and most likely indicates a programming error within rubyfmt, please
+    /// file a bug
+    IOError = 3,
+    /// some unknown ruby error occured during execution fo Rubyfmt. This indicates
+    /// a programming error. Please file a bug report and terminate the process
+    /// and restart.
+    OtherRubyError = 4,
+    /// the passed buffer wasn't valid utf-8. Non fatal, nothing was formatted.
+    InvalidUtf8 = 5,
+    /// the passed buffer declared an encoding in its magic comment that ruby
+    /// doesn't know, or contained bytes that aren't valid in that encoding.
+    /// Non fatal, nothing was formatted.
+    InvalidEncoding = 6,
+    /// rubyfmt panicked. This is a bug, please report it along with the
+    /// message from `rubyfmt_last_error_message`. A panic while formatting
+    /// leaves rubyfmt usable, but one while starting the Ruby VM means every
+    /// later call fails with this error too.
+    InternalPanic = 7,
+    /// the Ruby VM couldn't be started, or was started on another thread and
+    /// can only be used from that one. Nothing was formatted.
+    InitFailed = 8,
+    /// the passed `RubyfmtOptions` can't be formatted with, like a zero
+    /// `line_width` or `indent_width`. Nothing was formatted.
+    InvalidOptions = 9,
+}
+
+// Everything a C ABI function can fail with
+pub(crate) enum FfiError {
+    Format(RichFormatError),
+    Panic(String),
+    InvalidOptions(String),
+}
+
+impl FfiError {
+    fn code(self) -> FormatError {
+        match self {
+            FfiError::Format(e) => e.into_format_error(),
+            FfiError::Panic(_) => FormatError::InternalPanic,
+            FfiError::InvalidOptions(_) => FormatError::InvalidOptions,
+        }
+    }
+}
+
+impl fmt::Display for FfiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FfiError::Format(e) => write!(f, "{}", e),
+            FfiError::Panic(message) => write!(f, "rubyfmt panicked: {}", message),
+            FfiError::InvalidOptions(message) => write!(f, "invalid options: {}", message),
+        }
+    }
+}
+
+/// Options for `rubyfmt_format_buffer_with_options`. Start from
+/// `rubyfmt_default_options()` rather than zero initialising this, so new
+/// options keep their defaults.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct RubyfmtOptions {
+    pub line_width: usize,
+    pub indent_width: u32,
+    /// use single quotes for strings without escapes or interpolation
+    pub prefer_single_quotes: bool,
+    /// add trailing commas to collections broken over multiple lines
+    pub trailing_commas: bool,
+    /// drop the blank lines the user wrote between statements
+    pub remove_blank_lines: bool,
+    /// 0 keeps the input's line endings, 1 forces LF and 2 forces CRLF
+    pub line_endings: u32,
+    /// line up the trailing comments on consecutive lines in to one column
+    pub align_trailing_comments: bool,
+}
+
+impl From<FormatOptions> for RubyfmtOptions {
+    fn from(opts: FormatOptions) -> Self {
+        RubyfmtOptions {
+            line_width: opts.line_width,
+            indent_width: opts.indent_width,
+            prefer_single_quotes: opts.quote_style == QuoteStyle::PreferSingle,
+            trailing_commas: opts.trailing_commas == TrailingCommas::Multiline,
+            remove_blank_lines: opts.blank_lines == BlankLinePolicy::Remove,
+            line_endings: match opts.line_endings {
+                LineEndings::Preserve => 0,
+                LineEndings::Lf => 1,
+                LineEndings::Crlf => 2,
+            },
+            align_trailing_comments: opts.comment_alignment == CommentAlignment::Consecutive,
+        }
+    }
+}
+
+// the zero widths a zero initialised struct has would make every line too
+// long, and indent nothing, so those are rejected rather than formatted with
+fn format_options(opts: RubyfmtOptions) -> Result<FormatOptions, FfiError> {
+    if opts.line_width == 0 {
+        return Err(FfiError::InvalidOptions(
+            "line_width must be greater than 0".to_string(),
+        ));
+    }
+    if opts.indent_width == 0 {
+        return Err(FfiError::InvalidOptions(
+            "indent_width must be greater than 0".to_string(),
+        ));
+    }
+
+    Ok(FormatOptions {
+        line_width: opts.line_width,
+        indent_width: opts.indent_width,
+        quote_style: if opts.prefer_single_quotes {
+            QuoteStyle::PreferSingle
+        } else {
+            QuoteStyle::Double
+        },
+        trailing_commas: if opts.trailing_commas {
+            TrailingCommas::Multiline
+        } else {
+            TrailingCommas::Never
+        },
+        blank_lines: if opts.remove_blank_lines {
+            BlankLinePolicy::Remove
+        } else {
+            BlankLinePolicy::Preserve
+        },
+        line_endings: match opts.line_endings {
+            1 => LineEndings::Lf,
+            2 => LineEndings::Crlf,
+            _ => LineEndings::Preserve,
+        },
+        comment_alignment: if opts.align_trailing_comments {
+            CommentAlignment::Consecutive
+        } else {
+            CommentAlignment::Never
+        },
+    })
+}
+
+thread_local! {
+    static LAST_ERROR_MESSAGE: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+// Set if we panicked while starting the VM. We can't know how far ruby_setup
+// got, so nothing is allowed to touch the VM after that. Panics while
+// formatting don't set this: they come from our own rust code, and ruby is
+// never part way through a call when they unwind, so the VM is still fine.
+static VM_POISONED: AtomicBool = AtomicBool::new(false);
+
+const VM_POISONED_MESSAGE: &str = "an earlier call panicked while starting the Ruby VM";
+
+// Runs the body of a C ABI function, turning a panic in to an error. Letting
+// a panic unwind in to C is undefined behaviour.
+pub(crate) fn catch_panic<T, F>(f: F) -> Result<T, FfiError>
+where
+    F: FnOnce() -> Result<T, RichFormatError>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(res) => res.map_err(FfiError::Format),
+        Err(payload) => Err(FfiError::Panic(panic_message(&*payload))),
+    }
+}
+
+// Like catch_panic, for code that talks to the Ruby VM, which fails fast if
+// the VM is poisoned
+pub(crate) fn with_vm<T, F>(f: F) -> Result<T, FfiError>
+where
+    F: FnOnce() -> Result<T, RichFormatError>,
+{
+    if VM_POISONED.load(Ordering::SeqCst) {
+        return Err(FfiError::Panic(VM_POISONED_MESSAGE.to_string()));
+    }
+    catch_panic(|| {
+        #[cfg(feature = "test_hooks")]
+        if PANIC_NEXT_CALL.swap(false, Ordering::SeqCst) {
+            panic!("rubyfmt_test_panic_next_call was called");
+        }
+        f()
+    })
+}
+
+#[cfg(feature = "test_hooks")]
+static PANIC_NEXT_CALL: AtomicBool = AtomicBool::new(false);
+
+/// Makes the next call that uses the Ruby VM panic, which is how the tests
+/// reach `RUBYFMT_FORMAT_ERROR_INTERNAL_PANIC`. Making `rubyfmt_init` panic
+/// this way poisons the VM for the rest of the process. Only exported by
+/// builds with the `test_hooks` feature.
+#[cfg(feature = "test_hooks")]
+#[no_mangle]
+pub extern "C" fn rubyfmt_test_panic_next_call() {
+    PANIC_NEXT_CALL.store(true, Ordering::SeqCst);
+}
+
+// Rust options out of a possibly null RubyfmtOptions pointer
+unsafe fn read_options(options: *const RubyfmtOptions) -> Result<FormatOptions, FfiError> {
+    if options.is_null() {
+        Ok(FormatOptions::default())
+    } else {
+        format_options(*options)
+    }
+}
+
+/// Initialises the Ruby VM. Only the first call does anything, later calls
+/// report how that first one went.
+#[no_mangle]
+pub extern "C" fn rubyfmt_init() -> libc::c_int {
+    let res = with_vm(|| formatter::init_once().map_err(RichFormatError::from));
+    if let Err(FfiError::Panic(_)) = res {
+        VM_POISONED.store(true, Ordering::SeqCst);
+    }
+    match record_last_error(res) {
+        Ok(_) => InitStatus::OK as libc::c_int,
+        Err(_) => InitStatus::ERROR as libc::c_int,
+    }
+}
+
+/// # Safety
+/// this function will fail, very badly, if len specifies more bytes than is
+/// available in the passed buffer pointer. Data that isn't utf8, or in the
+/// encoding its magic comment declares, is reported as
+/// `FormatError::InvalidUtf8` or `FormatError::InvalidEncoding`. The output
+/// is in the same encoding as the input.
+/// Please don't pass too small buffers.
+#[no_mangle]
+pub unsafe extern "C" fn rubyfmt_format_buffer(
+    ptr: *const u8,
+    len: usize,
+    err: *mut i64,
+) -> *mut RubyfmtString {
+    rubyfmt_format_buffer_with_options(ptr, len, std::ptr::null(), err)
+}
+
+/// Like `rubyfmt_format_buffer`, formatting with the passed options. A null
+/// options pointer formats with the defaults.
+///
+/// # Safety
+/// the same rules as `rubyfmt_format_buffer` apply, and options must either
+/// be null or point to a valid `RubyfmtOptions`
+#[no_mangle]
+pub unsafe extern "C" fn rubyfmt_format_buffer_with_options(
+    ptr: *const u8,
+    len: usize,
+    options: *const RubyfmtOptions,
+    err: *mut i64,
+) -> *mut RubyfmtString {
+    let output = read_options(options).and_then(|options| {
+        with_vm(|| file_io::format_bytes(slice::from_raw_parts(ptr, len), &options))
+    });
+    match record_ffi_result(output, err) {
+        Some(o) => Box::into_raw(Box::new(RubyfmtString(o.into_boxed_slice()))),
+        None => std::ptr::null::<RubyfmtString>() as _,
+    }
+}
+
+/// Returns true if formatting the passed buffer wouldn't change it, without
+/// allocating a `RubyfmtString`. Returns false and populates err if the
+/// buffer couldn't be formatted.
+///
+/// # Safety
+/// the same rules as `rubyfmt_format_buffer_with_options` apply
+#[no_mangle]
+pub unsafe extern "C" fn rubyfmt_is_formatted(
+    ptr: *const u8,
+    len: usize,
+    options: *const RubyfmtOptions,
+    err: *mut i64,
+) -> bool {
+    let input = slice::from_raw_parts(ptr, len);
+    let is_formatted = read_options(options).and_then(|options| {
+        with_vm(|| file_io::format_bytes(input, &options).map(|output| output == input))
+    });
+    record_ffi_result(is_formatted, err).unwrap_or(false)
+}
+
+/// The options `rubyfmt_format_buffer` formats with
+#[no_mangle]
+pub extern "C" fn rubyfmt_default_options() -> RubyfmtOptions {
+    // nothing in here can panic, but just in case, fall back to the zeroed
+    // options the docs tell people not to use rather than unwinding
+    record_last_error(catch_panic(|| {
+        Ok(RubyfmtOptions::from(FormatOptions::default()))
+    }))
+    .unwrap_or(RubyfmtOptions {
+        line_width: 0,
+        indent_width: 0,
+        prefer_single_quotes: false,
+        trailing_commas: false,
+        remove_blank_lines: false,
+        line_endings: 0,
+        align_trailing_comments: false,
+    })
+}
+
+/// A description of the last error a rubyfmt function returned on this
+/// thread, or null if the last call succeeded. Syntax errors are reported as
+/// `line:column: message`, one per line, and panics as `rubyfmt panicked:`
+/// followed by the panic message. The string is owned by rubyfmt, and is
+/// valid until the next rubyfmt call on this thread.
+#[no_mangle]
+pub extern "C" fn rubyfmt_last_error_message() -> *const libc::c_char {
+    catch_panic(|| {
+        Ok(LAST_ERROR_MESSAGE.with(|message| match &*message.borrow() {
+            Some(message) => message.as_ptr(),
+            None => std::ptr::null(),
+        }))
+    })
+    .unwrap_or(std::ptr::null())
+}
+
+/// The version of rubyfmt, as a null terminated string
+#[no_mangle]
+pub extern "C" fn rubyfmt_version() -> *const libc::c_char {
+    record_last_error(catch_panic(|| {
+        Ok(concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as _)
+    }))
+    .unwrap_or(std::ptr::null())
+}
+
+// writes the error code for a C ABI call to err, and remembers the error
+// message for rubyfmt_last_error_message
+unsafe fn record_ffi_result<T>(res: Result<T, FfiError>, err: *mut i64) -> Option<T> {
+    match record_last_error(res) {
+        Ok(t) => {
+            *err = FormatError::OK as i64;
+            Some(t)
+        }
+        Err(e) => {
+            *err = e.code() as i64;
+            None
+        }
+    }
+}
+
+// remembers how a C ABI call went for rubyfmt_last_error_message, clearing
+// the message from any earlier failure if this call succeeded
+fn record_last_error<T>(res: Result<T, FfiError>) -> Result<T, FfiError> {
+    match &res {
+        Ok(_) => set_last_error_message(None),
+        Err(e) => set_last_error_message(Some(e.to_string())),
+    }
+    res
+}
+
+fn set_last_error_message(message: Option<String>) {
+    let message = message
+        .map(|message| CString::new(message.replace('\0', "")).expect("nul bytes were removed"));
+    LAST_ERROR_MESSAGE.with(|last| *last.borrow_mut() = message);
+}
+
+#[no_mangle]
+pub extern "C" fn rubyfmt_string_ptr(s: &RubyfmtString) -> *const u8 {
+    record_last_error(catch_panic(|| Ok(s.0.as_ptr()))).unwrap_or(std::ptr::null())
+}
+
+#[no_mangle]
+pub extern "C" fn rubyfmt_string_len(s: &RubyfmtString) -> usize {
+    record_last_error(catch_panic(|| Ok(s.0.len()))).unwrap_or(0)
+}
+
+/// # Safety
+/// rubyfmt_string must have come from `rubyfmt_format_buffer`, and must not
+/// be used after it's been freed
+#[no_mangle]
+pub unsafe extern "C" fn rubyfmt_string_free(rubyfmt_string: *mut RubyfmtString) {
+    let _ = record_last_error(catch_panic(|| {
+        drop(Box::from_raw(rubyfmt_string));
+        Ok(())
+    }));
+}
@@ -1,14 +1,62 @@
 use std::collections::BTreeMap;
 use std::mem;
+use std::ops::{Range, RangeInclusive};
 
-use crate::comment_block::CommentBlock;
+use crate::comment_block::{line_count, CommentBlock};
 use crate::ruby::*;
-use crate::types::LineNumber;
+use crate::types::{ColNumber, LineNumber};
 
-#[derive(Debug, Default)]
+/// Where a comment sat on its line in the source
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CommentPlacement {
+    /// Only whitespace came before the comment
+    OwnLine,
+    /// The comment came after some code, like `foo(1) # explain`
+    Trailing,
+}
+
+#[derive(Debug, Clone)]
+pub struct Comment {
+    pub contents: String,
+    pub column: ColNumber,
+    pub placement: CommentPlacement,
+}
+
+/// A comment that stops rubyfmt from formatting some statements, which are
+/// written out exactly as they were in the source instead
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Directive {
+    /// `# rubyfmt:disable`, which lasts until the next `# rubyfmt:enable`
+    Disable,
+    /// `# rubyfmt:enable`
+    Enable,
+    /// `# rubyfmt:skip`, which only covers the statement after it
+    Skip,
+}
+
+impl Directive {
+    fn from_comment(contents: &str) -> Option<Self> {
+        match contents.trim_start_matches('#').trim() {
+            "rubyfmt:disable" => Some(Directive::Disable),
+            "rubyfmt:enable" => Some(Directive::Enable),
+            "rubyfmt:skip" => Some(Directive::Skip),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
 pub struct FileComments {
     start_of_file_contiguous_comment_lines: Option<CommentBlock>,
     other_comments: BTreeMap<LineNumber, String>,
+    trailing_comments: BTreeMap<LineNumber, Comment>,
+    directives: BTreeMap<LineNumber, Directive>,
+    // the first and last line of every statement a directive covers, keyed
+    // by the first
+    protected_statements: BTreeMap<LineNumber, LineNumber>,
+    // the lines of the source, which we only hold on to if some statements
+    // are protected
+    protected_source: Vec<String>,
 }
 
 impl FileComments {
@@ -28,10 +76,32 @@ impl FileComments {
             if lineno < 0 {
                 raise("line number negative");
             }
-            let comment = unsafe { ruby_string_to_str(*ruby_comment) }
-                .trim()
-                .to_owned();
-            fc.push_comment(lineno as _, comment);
+            let comment = match unsafe { ruby_array_to_slice(*ruby_comment) } {
+                [contents, column, own_line] => Comment {
+                    contents: unsafe { ruby_string_to_str(*contents) }.trim().to_owned(),
+                    column: unsafe { rubyfmt_rb_num2ll(*column) } as _,
+                    placement: if *own_line == Qtrue {
+                        CommentPlacement::OwnLine
+                    } else {
+                        CommentPlacement::Trailing
+                    },
+                },
+                other => panic!(
+                    "expected comment to contain three elements, actually got: {}",
+                    other.len(),
+                ),
+            };
+            match comment.placement {
+                CommentPlacement::OwnLine => {
+                    if let Some(directive) = Directive::from_comment(&comment.contents) {
+                        fc.directives.insert(lineno as _, directive);
+                    }
+                    fc.push_comment(lineno as _, comment.contents)
+                }
+                CommentPlacement::Trailing => {
+                    fc.trailing_comments.insert(lineno as _, comment);
+                }
+            }
         }
         fc
     }
@@ -50,8 +120,9 @@ impl FileComments {
                     "If we have a start of file sled, it needs to come first,
                      otherwise we won't know where the last line is",
                 );
+                let end = 1 + line_count(&l);
                 self.start_of_file_contiguous_comment_lines =
-                    Some(CommentBlock::new(1..2, vec![l]));
+                    Some(CommentBlock::new(1..end, vec![l]));
             }
             (Some(sled), _) if sled.following_line_number() == line_number => {
                 sled.add_line(l);
@@ -62,10 +133,109 @@ impl FileComments {
         }
     }
 
+    pub fn start_of_file_comment_lines(&self) -> impl Iterator<Item = &str> {
+        self.start_of_file_contiguous_comment_lines
+            .iter()
+            .flat_map(|block| block.comments())
+            .flat_map(|comment| comment.lines())
+    }
+
     pub fn take_start_of_file_contiguous_comment_lines(&mut self) -> Option<CommentBlock> {
         self.start_of_file_contiguous_comment_lines.take()
     }
 
+    /// Takes the trailing comments from every line before `line_number`,
+    /// which by now have had all of their code emitted
+    pub fn extract_trailing_comments_before_line(
+        &mut self,
+        line_number: LineNumber,
+    ) -> Vec<Comment> {
+        let remaining_comments = self.trailing_comments.split_off(&line_number);
+        mem::replace(&mut self.trailing_comments, remaining_comments)
+            .into_values()
+            .collect()
+    }
+
+    /// Works out which statements the directives in this file cover, given
+    /// the lines each statement in `source` starts and ends on
+    pub fn protect_statements(
+        &mut self,
+        statement_spans: &BTreeMap<LineNumber, LineNumber>,
+        source: &str,
+    ) {
+        let mut disabled_from = None;
+        for (&line_number, &directive) in self.directives.iter() {
+            match (directive, disabled_from) {
+                (Directive::Disable, None) => disabled_from = Some(line_number),
+                (Directive::Enable, Some(start)) => {
+                    self.protected_statements
+                        .extend(statement_spans.range(start..line_number));
+                    disabled_from = None;
+                }
+                (Directive::Skip, None) => {
+                    // a skip in the middle of a statement, like between a
+                    // call's arguments, has no statement of its own to skip
+                    let next = statement_spans.range(line_number..).next();
+                    self.protected_statements
+                        .extend(next.filter(|&(&first, _)| {
+                            !statement_spans
+                                .range(..line_number)
+                                .any(|(_, &last)| line_number < last && last < first)
+                        }));
+                }
+                _ => {}
+            }
+        }
+        if let Some(start) = disabled_from {
+            self.protected_statements
+                .extend(statement_spans.range(start..));
+        }
+
+        if !self.protected_statements.is_empty() {
+            self.protected_source = source.split_inclusive('\n').map(str::to_owned).collect();
+        }
+    }
+
+    /// Whether any of `lines` has a comment on its own line that hasn't been
+    /// extracted yet
+    pub fn has_comments_in_lines(&self, lines: Range<LineNumber>) -> bool {
+        self.other_comments.range(lines).next().is_some()
+    }
+
+    pub fn has_protected_statements(&self) -> bool {
+        !self.protected_statements.is_empty()
+    }
+
+    /// The lines of the protected statement starting on `line_number`, if
+    /// there is one
+    pub fn protected_statement_lines(
+        &self,
+        line_number: LineNumber,
+    ) -> Option<RangeInclusive<LineNumber>> {
+        self.protected_statements
+            .get(&line_number)
+            .map(|&last_line| line_number..=last_line)
+    }
+
+    /// Takes the source of a protected statement, dropping the comments on
+    /// its lines, since the source already has them in it
+    pub fn take_protected_source(&mut self, lines: &RangeInclusive<LineNumber>) -> String {
+        self.other_comments
+            .retain(|line_number, _| !lines.contains(line_number));
+        self.trailing_comments
+            .retain(|line_number, _| !lines.contains(line_number));
+
+        let last_line = (*lines.end() as usize).min(self.protected_source.len());
+        let first_line = (*lines.start() as usize).min(last_line + 1);
+        let source = self.protected_source[first_line - 1..last_line].concat();
+        // the line ending after the statement is written by the formatter
+        let statement = source
+            .strip_suffix("\r\n")
+            .or_else(|| source.strip_suffix('\n'))
+            .unwrap_or(&source);
+        statement.to_owned()
+    }
+
     pub fn extract_comments_to_line(&mut self, line_number: LineNumber) -> Option<CommentBlock> {
         self.other_comments
             .keys()
@@ -74,8 +244,7 @@ impl FileComments {
             .map(|lowest_line| {
                 let remaining_comments = self.other_comments.split_off(&(line_number + 1));
                 let comments = mem::replace(&mut self.other_comments, remaining_comments)
-                    .into_iter()
-                    .map(|(_, v)| v)
+                    .into_values()
                     .collect();
                 CommentBlock::new(lowest_line..line_number + 1, comments)
             })
@@ -0,0 +1,73 @@
+use crate::format_options::FormatOptions;
+use crate::source_encoding::SourceEncoding;
+use crate::{format_buffer_with_options, RichFormatError};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Where `format_file` should put the formatted output
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatTarget {
+    // overwrite the input file, if formatting changed it
+    InPlace,
+    Stdout,
+    // write the output to a different file, creating it if needed
+    Path(PathBuf),
+}
+
+/// Reads all of `reader`, formats it, and writes the result to `writer`.
+/// Input in an encoding other than UTF-8 needs a magic comment saying so, and
+/// is written back in that encoding.
+pub fn format_reader<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    opts: &FormatOptions,
+) -> Result<(), RichFormatError> {
+    let mut input = vec![];
+    reader
+        .read_to_end(&mut input)
+        .map_err(RichFormatError::IOError)?;
+    let output = format_bytes(&input, opts)?;
+    writer
+        .write_all(&output)
+        .map_err(RichFormatError::IOError)?;
+    writer.flush().map_err(RichFormatError::IOError)
+}
+
+/// Formats the file at `path`, writing the output to `target`. Formatting a
+/// file in place that's already formatted leaves it untouched.
+pub fn format_file<P: AsRef<Path>>(
+    path: P,
+    target: FormatTarget,
+    opts: &FormatOptions,
+) -> Result<(), RichFormatError> {
+    let path = path.as_ref();
+    let input = fs::read(path).map_err(RichFormatError::IOError)?;
+    let output = format_bytes(&input, opts)?;
+
+    match target {
+        FormatTarget::InPlace => {
+            if output != input {
+                fs::write(path, output).map_err(RichFormatError::IOError)?;
+            }
+            Ok(())
+        }
+        FormatTarget::Stdout => {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            stdout
+                .write_all(&output)
+                .map_err(RichFormatError::IOError)?;
+            stdout.flush().map_err(RichFormatError::IOError)
+        }
+        FormatTarget::Path(out_path) => {
+            fs::write(out_path, output).map_err(RichFormatError::IOError)
+        }
+    }
+}
+
+pub(crate) fn format_bytes(input: &[u8], opts: &FormatOptions) -> Result<Vec<u8>, RichFormatError> {
+    let (buf, encoding) = SourceEncoding::decode(input)?;
+    let output = format_buffer_with_options(&buf, opts)?;
+    encoding.encode(output)
+}
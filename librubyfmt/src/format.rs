@@ -1,18 +1,36 @@
 use crate::delimiters::BreakableDelims;
+use crate::format_options::{QuoteStyle, TrailingCommas};
 use crate::parser_state::{FormattingContext, ParserState};
 use crate::ripper_tree_types::*;
+use crate::types::LineNumber;
 use log::debug;
+use std::collections::BTreeMap;
 
 pub fn format_def(ps: &mut ParserState, def: Def) {
     let def_expression = (def.1).to_def_parts();
+    let params = def.2;
 
-    let body = def.3;
     ps.on_line((def_expression.1).0);
     if ps.at_start_of_line() {
         ps.emit_indent();
     }
-    ps.emit_def(def_expression.0);
-    format_paren_or_params(ps, def.2);
+
+    let format_head = |ps: &mut ParserState| {
+        ps.emit_def(def_expression.0.clone());
+        format_paren_or_params(ps, params.clone());
+    };
+    // an endless def that doesn't fit on one line falls back to a regular
+    // `def ... end`, rather than an endless one with its body broken over
+    // several lines. It only goes that way: a regular def is never collapsed
+    // in to an endless one, even if it'd fit
+    let body = match def.3 {
+        DefBody::BodyStmt(body) => body,
+        DefBody::Endless(endless) => match format_endless_def(ps, &format_head, endless) {
+            Some(body) => body,
+            None => return,
+        },
+    };
+    format_head(ps);
 
     ps.with_formatting_context(FormattingContext::Def, |ps| {
         ps.new_block(|ps| {
@@ -30,6 +48,46 @@ pub fn format_def(ps: &mut ParserState, def: Def) {
     ps.emit_newline();
 }
 
+// an endless method, `def foo(bar) = expression`, stays that way when it
+// fits on one line. Otherwise this hands back its body, to be written out as
+// a regular method
+fn format_endless_def<F>(
+    ps: &mut ParserState,
+    format_head: &F,
+    endless: EndlessBodyStmt,
+) -> Option<Box<BodyStmt>>
+where
+    F: Fn(&mut ParserState),
+{
+    let body = endless.1.clone();
+    let is_multiline = ps.will_render_as_multiline(|next_ps| {
+        next_ps.emit_indent();
+        format_head(next_ps);
+        format_endless_def_body(next_ps, body);
+    });
+
+    if is_multiline {
+        return Some(endless.into_bodystmt());
+    }
+
+    format_head(ps);
+    format_endless_def_body(ps, endless.1);
+    if ps.at_start_of_line() {
+        ps.emit_newline();
+    }
+    None
+}
+
+fn format_endless_def_body(ps: &mut ParserState, body: Box<Expression>) {
+    ps.emit_space();
+    ps.emit_op("=".to_string());
+    ps.emit_space();
+
+    ps.with_formatting_context(FormattingContext::Def, |ps| {
+        ps.with_start_of_line(false, |ps| format_expression(ps, *body));
+    });
+}
+
 pub fn inner_format_params(ps: &mut ParserState, params: Box<Params>) {
     let non_null_positions = params.non_null_positions();
     //def foo(a, b=nil, *args, d, e:, **kwargs, &blk)
@@ -155,7 +213,9 @@ pub fn format_block_arg(ps: &mut ParserState, block_arg: Option<BlockArg>) -> bo
     ps.with_start_of_line(false, |ps| {
         ps.emit_soft_indent();
         ps.emit_ident("&".to_string());
-        format_ident(ps, block_arg.unwrap().1);
+        if let Some(ident) = block_arg.unwrap().1 {
+            format_ident(ps, ident);
+        }
     });
 
     true
@@ -194,6 +254,11 @@ pub fn format_rest_param(
         None => false,
         Some(RestParamOr0OrExcessedComma::ExcessedComma(_)) => false,
         Some(RestParamOr0OrExcessedComma::Zero(_)) => false,
+        Some(RestParamOr0OrExcessedComma::ArgsForward(_)) => {
+            ps.emit_soft_indent();
+            ps.emit_ident("...".to_string());
+            true
+        }
         Some(RestParamOr0OrExcessedComma::RestParam(rp)) => {
             ps.emit_soft_indent();
             ps.emit_ident("*".to_string());
@@ -452,6 +517,20 @@ pub fn use_parens_for_method_call(
 ) -> bool {
     let name = method.get_name();
     debug!("name: {:?}", name);
+    // `foo ...` is an endless range, not argument forwarding, and `foo x:`
+    // would take whatever's on the next line as the value of x
+    match args.last_expression() {
+        Some(Expression::ArgsForward(..)) => return true,
+        Some(Expression::BareAssocHash(bah)) => {
+            if let Some(AssocNewOrAssocSplat::AssocNew(new)) = bah.1.last() {
+                if new.2.is_none() {
+                    return true;
+                }
+            }
+        }
+        _ => {}
+    }
+
     if name.starts_with("attr_") && context == FormattingContext::ClassOrModule {
         return false;
     }
@@ -521,7 +600,7 @@ pub fn format_method_call(ps: &mut ParserState, method_call: MethodCall) {
         ps.emit_indent();
     }
 
-    let (chain, method, original_used_parens, args) =
+    let (mut chain, method, original_used_parens, args) =
         (method_call.1, method_call.2, method_call.3, method_call.4);
 
     debug!("method call!!");
@@ -533,27 +612,41 @@ pub fn format_method_call(ps: &mut ParserState, method_call: MethodCall) {
         ps.current_formatting_context(),
     );
 
+    // the method name goes in the chain, to start the chain's last link
+    chain.push(CallChainElement::IdentOrOpOrKeywordOrConst(method));
     ps.with_start_of_line(false, |ps| {
-        format_call_chain(ps, chain);
-        format_ident(ps, method.into_ident());
-
-        let delims = if use_parens {
-            BreakableDelims::for_method_call()
-        } else {
-            BreakableDelims::for_kw()
-        };
+        format_call_chain(ps, chain, |ps| {
+            let delims = if use_parens {
+                BreakableDelims::for_method_call()
+            } else {
+                BreakableDelims::for_kw()
+            };
 
-        if !args.is_empty() {
-            ps.breakable_of(delims, |ps| {
-                ps.with_formatting_context(FormattingContext::ArgsList, |ps| {
-                    format_list_like_thing(ps, args, false);
-                    ps.emit_collapsing_newline();
+            if !args.is_empty() {
+                // a trailing comma after a block arg, a bare hash or `...` isn't
+                // valid ruby (or doesn't land after the last argument), and
+                // without parens it would turn the next line into another argument
+                let trailing_comma = use_parens
+                    && !matches!(
+                        args.last_expression(),
+                        Some(Expression::ToProc(..))
+                            | Some(Expression::BareAssocHash(..))
+                            | Some(Expression::ArgsForward(..))
+                    );
+                ps.breakable_of(delims, |ps| {
+                    ps.with_formatting_context(FormattingContext::ArgsList, |ps| {
+                        format_list_like_thing(ps, args, false);
+                        if trailing_comma {
+                            emit_trailing_comma(ps);
+                        }
+                        ps.emit_collapsing_newline();
+                    });
                 });
-            });
-        } else if use_parens {
-            ps.emit_open_paren();
-            ps.emit_close_paren();
-        }
+            } else if use_parens {
+                ps.emit_open_paren();
+                ps.emit_close_paren();
+            }
+        });
     });
 
     if ps.at_start_of_line() {
@@ -561,10 +654,20 @@ pub fn format_method_call(ps: &mut ParserState, method_call: MethodCall) {
     }
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SpecialCase {
     NoSpecialCase,
     NoLeadingTrailingCollectionMarkers,
+    HashLiteral,
+}
+
+// only shows up in multiline output, single line breakables strip
+// trailing commas before their closing delimiter
+fn emit_trailing_comma(ps: &mut ParserState) {
+    if ps.options().trailing_commas == TrailingCommas::Multiline {
+        ps.emit_comma();
+    }
 }
 
 pub fn format_list_like_thing_items(
@@ -575,7 +678,7 @@ pub fn format_list_like_thing_items(
     let mut emitted_args = false;
     let args_count = args.len();
 
-    ps.magic_handle_comments_for_mulitiline_arrays(|ps| {
+    ps.handle_comments_for_multiline_breakable(|ps| {
         for (idx, expr) in args.into_iter().enumerate() {
             // this raise was present in the ruby source code of rubyfmt
             // but I'm pretty sure it's categorically impossible now. Thanks
@@ -734,24 +837,30 @@ pub fn format_assocs(ps: &mut ParserState, assocs: Vec<AssocNewOrAssocSplat>, sc
                 match new.1 {
                     AssocKey::Label(label) => {
                         handle_string_and_linecol(ps, label.1, label.2);
-                        ps.emit_space();
                     }
                     AssocKey::Expression(expression) => {
                         format_expression(ps, expression);
                         ps.emit_space();
                         ps.emit_ident("=>".to_string());
-                        ps.emit_space();
                     }
                 }
-                format_expression(ps, new.2);
+                // the value is left out of `{x:, y:}`
+                if let Some(value) = new.2 {
+                    ps.emit_space();
+                    format_expression(ps, value);
+                }
             }
             AssocNewOrAssocSplat::AssocSplat(splat) => {
                 ps.emit_ident("**".to_string());
-                format_expression(ps, splat.1);
+                if let Some(splat) = splat.1 {
+                    format_expression(ps, splat);
+                }
             }
         });
         if idx != len - 1 {
             ps.emit_comma();
+        } else if sc == SpecialCase::HashLiteral {
+            emit_trailing_comma(ps);
         }
         ps.emit_soft_newline();
     }
@@ -978,6 +1087,7 @@ pub fn format_array_fast_path(ps: &mut ParserState, a: Option<ArgsAddStarOrExpre
         Some(a) => {
             ps.breakable_of(BreakableDelims::for_array(), |ps| {
                 format_list_like_thing(ps, a, false);
+                emit_trailing_comma(ps);
                 ps.emit_collapsing_newline();
 
                 if ps.last_breakable_is_multiline() {
@@ -1022,7 +1132,9 @@ pub fn format_list_like_thing(
 
             ps.with_start_of_line(false, |ps| {
                 ps.emit_ident("*".to_string());
-                format_expression(ps, *star);
+                if let Some(star) = star {
+                    format_expression(ps, *star);
+                }
 
                 for expr in right {
                     emit_intermediate_array_separator(ps, single_line);
@@ -1131,9 +1243,15 @@ pub fn format_string_literal(ps: &mut ParserState, sl: StringLiteral) {
                 ps.emit_indent();
             }
 
-            ps.emit_double_quote();
-            format_inner_string(ps, parts, StringType::Quoted);
-            ps.emit_double_quote();
+            if can_use_single_quotes(ps, &parts) {
+                ps.emit_single_quote();
+                format_inner_string(ps, parts, StringType::Quoted);
+                ps.emit_single_quote();
+            } else {
+                ps.emit_double_quote();
+                format_inner_string(ps, parts, StringType::Quoted);
+                ps.emit_double_quote();
+            }
 
             if ps.at_start_of_line() {
                 ps.emit_newline();
@@ -1142,6 +1260,16 @@ pub fn format_string_literal(ps: &mut ParserState, sl: StringLiteral) {
     }
 }
 
+// string content arrives escaped for double quotes, so it's only safe to
+// swap the quotes if there's nothing interpolated or escaped in it
+fn can_use_single_quotes(ps: &ParserState, parts: &[StringContentPart]) -> bool {
+    ps.options().quote_style == QuoteStyle::PreferSingle
+        && parts.iter().all(|part| match part {
+            StringContentPart::TStringContent(t) => !t.1.contains('\\') && !t.1.contains('\''),
+            _ => false,
+        })
+}
+
 pub fn format_xstring_literal(ps: &mut ParserState, xsl: XStringLiteral) {
     let parts = xsl.1;
 
@@ -1573,35 +1701,62 @@ pub fn format_undef(ps: &mut ParserState, undef: Undef) {
     }
 }
 
+pub fn format_rightward_assign(ps: &mut ParserState, ra: RightwardAssign) {
+    if ps.at_start_of_line() {
+        ps.emit_indent();
+    }
+    ps.on_line((ra.3).0);
+
+    ps.with_start_of_line(false, |ps| {
+        format_expression(ps, *ra.1);
+        ps.emit_space();
+        ps.emit_op("=>".to_string());
+        ps.emit_space();
+        format_pattern(ps, ra.2);
+    });
+
+    if ps.at_start_of_line() {
+        ps.emit_newline();
+    }
+}
+
 pub fn format_defs(ps: &mut ParserState, defs: Defs) {
     if ps.at_start_of_line() {
         ps.emit_indent();
     }
 
     let singleton = defs.1;
-    let ident_or_kw = defs.3;
+    let (ident, linecol) = defs.3.to_def_parts();
     let paren_or_params = defs.4;
-    let bodystmt = defs.5;
 
-    ps.emit_def_keyword();
-    ps.emit_space();
+    let format_head = |ps: &mut ParserState| {
+        ps.emit_def_keyword();
+        ps.emit_space();
 
-    ps.with_start_of_line(false, |ps| {
-        match singleton {
-            Singleton::VarRef(vr) => {
-                format_var_ref(ps, vr);
-            }
-            Singleton::Paren(pe) => {
-                format_paren(ps, pe);
+        ps.with_start_of_line(false, |ps| {
+            match singleton.clone() {
+                Singleton::VarRef(vr) => {
+                    format_var_ref(ps, vr);
+                }
+                Singleton::Paren(pe) => {
+                    format_paren(ps, pe);
+                }
             }
-        }
 
-        ps.emit_dot();
-        let (ident, linecol) = ident_or_kw.to_def_parts();
-        handle_string_and_linecol(ps, ident, linecol);
-        format_paren_or_params(ps, paren_or_params);
-        ps.emit_newline();
-    });
+            ps.emit_dot();
+            handle_string_and_linecol(ps, ident.clone(), linecol.clone());
+            format_paren_or_params(ps, paren_or_params.clone());
+        });
+    };
+    let bodystmt = match defs.5 {
+        DefBody::BodyStmt(body) => body,
+        DefBody::Endless(endless) => match format_endless_def(ps, &format_head, endless) {
+            Some(body) => body,
+            None => return,
+        },
+    };
+    format_head(ps);
+    ps.emit_newline();
 
     ps.with_formatting_context(FormattingContext::Def, |ps| {
         ps.new_block(|ps| {
@@ -1872,7 +2027,9 @@ pub fn format_hash(ps: &mut ParserState, hash: Hash) {
         None => ps.emit_ident("{}".to_string()),
         Some(assoc_list_from_args) => {
             ps.breakable_of(BreakableDelims::for_hash(), |ps| {
-                format_assocs(ps, assoc_list_from_args.1, SpecialCase::NoSpecialCase);
+                ps.handle_comments_for_multiline_breakable(|ps| {
+                    format_assocs(ps, assoc_list_from_args.1, SpecialCase::HashLiteral);
+                });
             });
         }
     };
@@ -1912,7 +2069,118 @@ pub fn format_backref(ps: &mut ParserState, backref: Backref) {
     }
 }
 
-fn format_call_chain(ps: &mut ParserState, cc: Vec<CallChainElement>) {
+// Formats a chain of calls, then `format_last_link`, which is the rest of
+// the chain's last link, like the arguments of the call at the end of it. A
+// chain with comments between its links is written over several lines, one
+// link per line, with each comment above the link it came before
+fn format_call_chain<F>(ps: &mut ParserState, cc: Vec<CallChainElement>, format_last_link: F)
+where
+    F: FnOnce(&mut ParserState),
+{
+    if !call_chain_has_comments_between_links(ps, &cc) {
+        format_call_chain_elements(ps, cc);
+        format_last_link(ps);
+        return;
+    }
+
+    let link_lines = call_chain_link_lines(&cc);
+    let mut elements = cc.into_iter().enumerate().peekable();
+    while let Some((_, element)) = elements.next_if(|(idx, _)| !link_lines.contains_key(idx)) {
+        format_call_chain_elements(ps, vec![element]);
+    }
+
+    ps.new_block(|ps| {
+        while let Some((idx, dot)) = elements.next() {
+            ps.emit_newline();
+            if let Some(&Some(line_number)) = link_lines.get(&idx) {
+                ps.shift_comments_before_line(line_number);
+            }
+            ps.emit_indent();
+
+            let mut link = vec![dot];
+            while let Some((_, element)) =
+                elements.next_if(|(idx, _)| !link_lines.contains_key(idx))
+            {
+                link.push(element);
+            }
+            format_call_chain_elements(ps, link);
+        }
+        format_last_link(ps);
+    });
+}
+
+// The line each link of a chain starts on, by the index of the dot that
+// starts it. A link written with the dot at the end of the line before, like
+// `foo.\n  bar`, starts on the line of its method name. 2.6 doesn't tell us
+// where its dots are, so a link without a method name has no line there
+fn call_chain_link_lines(cc: &[CallChainElement]) -> BTreeMap<usize, Option<LineNumber>> {
+    cc.iter()
+        .enumerate()
+        .filter_map(|(idx, element)| match element {
+            CallChainElement::DotTypeOrOp(DotTypeOrOp::ColonColon(_)) => None,
+            CallChainElement::DotTypeOrOp(dot) => {
+                let line_number = match cc.get(idx + 1) {
+                    Some(CallChainElement::IdentOrOpOrKeywordOrConst(name)) => {
+                        Some(name.clone().to_def_parts().1 .0)
+                    }
+                    _ => match dot {
+                        DotTypeOrOp::Op(op) => Some((op.2).0),
+                        DotTypeOrOp::Period(period) => Some((period.2).0),
+                        _ => None,
+                    },
+                };
+                Some((idx, line_number))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+// Whether there are comments on their own lines between the links of a
+// chain. Comments inside a block in the chain belong to the block, so this
+// formats the chain up to each link first, on a copy of the comments, and
+// only counts the ones still left before it
+fn call_chain_has_comments_between_links(ps: &ParserState, cc: &[CallChainElement]) -> bool {
+    // a comment before a leading `.` only parses from ruby 2.7 on, so on
+    // older grammars the comments stay above the statement
+    if !grammar_is_at_least(2, 7) {
+        return false;
+    }
+
+    let link_lines = call_chain_link_lines(cc);
+    let last_line = match link_lines.values().flatten().max() {
+        Some(&last_line) => last_line,
+        None => return false,
+    };
+    if !ps.has_comments_in_lines(0..last_line) {
+        return false;
+    }
+
+    let mut next_ps = ParserState::new_with_comments_from(ps);
+    let mut has_comments = false;
+    next_ps.with_start_of_line(false, |next_ps| {
+        for (idx, element) in cc.iter().enumerate() {
+            if let Some(&Some(line_number)) = link_lines.get(&idx) {
+                if next_ps.has_comments_in_lines(0..line_number) {
+                    has_comments = true;
+                    return;
+                }
+            }
+            format_call_chain_elements(next_ps, vec![element.clone()]);
+        }
+    });
+    has_comments
+}
+
+fn grammar_is_at_least(major: u32, minor: u32) -> bool {
+    let mut parts = crate::RUBY_VERSION
+        .split('.')
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let version = (parts.next().unwrap_or(0), parts.next().unwrap_or(0));
+    version >= (major, minor)
+}
+
+fn format_call_chain_elements(ps: &mut ParserState, cc: Vec<CallChainElement>) {
     for cc_elem in cc.into_iter() {
         match cc_elem {
             CallChainElement::Paren(p) => format_paren(ps, p),
@@ -1947,22 +2215,18 @@ pub fn format_method_add_block(ps: &mut ParserState, mab: MethodAddBlock) {
         ps.emit_indent();
     }
 
-    let chain = (mab.1).into_call_chain();
+    let (chain, block) = ((mab.1).into_call_chain(), mab.2);
 
     ps.with_start_of_line(false, |ps| {
-        format_call_chain(ps, chain);
+        format_call_chain(ps, chain, |ps| {
+            // safe to unconditionally emit a space here, we don't have to
+            // worry about not having a block, method_add_block can only be
+            // parsed if we do in fact have a block
+            ps.emit_space();
+            format_block(ps, block);
+        });
     });
 
-    // safe to unconditionally emit a space here, we don't have to worry
-    // about not having a block, method_add_block can only be parsed if we
-    // do in fact have a block
-    ps.emit_space();
-
-    match mab.2 {
-        Block::DoBlock(do_block) => format_do_block(ps, do_block),
-        Block::BraceBlock(brace_block) => format_brace_block(ps, brace_block),
-    }
-
     if ps.at_start_of_line() {
         ps.emit_newline();
     }
@@ -2220,10 +2484,19 @@ pub fn format_case(ps: &mut ParserState, case: Case) {
     }
     ps.on_line((case.3).0);
 
-    ps.emit_case_keyword();
-
     let case_expr = case.1;
-    let tail = case.2;
+    let tail = match case.2 {
+        WhenOrIn::In(in_) => {
+            if in_.2.is_none() {
+                format_one_line_pattern_match(ps, case_expr, *in_);
+                return;
+            }
+            WhenOrIn::In(in_)
+        }
+        tail => tail,
+    };
+
+    ps.emit_case_keyword();
 
     if let Some(e) = case_expr {
         ps.with_start_of_line(false, |ps| {
@@ -2234,7 +2507,10 @@ pub fn format_case(ps: &mut ParserState, case: Case) {
 
     ps.emit_newline();
     ps.with_start_of_line(true, |ps| {
-        format_when_or_else(ps, WhenOrElse::When(tail));
+        match tail {
+            WhenOrIn::When(when) => format_when_or_else(ps, WhenOrElse::When(when)),
+            WhenOrIn::In(in_) => format_in_or_else(ps, InOrElse::In(*in_)),
+        }
         ps.emit_end();
     });
 
@@ -2243,6 +2519,226 @@ pub fn format_case(ps: &mut ParserState, case: Case) {
     }
 }
 
+// `expr in pattern`, which ripper hands us as a case with a single bodyless in
+pub fn format_one_line_pattern_match(ps: &mut ParserState, expr: Option<Box<Expression>>, in_: In) {
+    ps.with_start_of_line(false, |ps| {
+        if let Some(e) = expr {
+            format_expression(ps, *e);
+        }
+        ps.emit_space();
+        ps.emit_in_keyword();
+        ps.emit_space();
+        format_guarded_pattern(ps, in_.1);
+    });
+
+    if ps.at_start_of_line() {
+        ps.emit_newline();
+    }
+}
+
+pub fn format_in_or_else(ps: &mut ParserState, tail: InOrElse) {
+    match tail {
+        InOrElse::In(in_) => {
+            let pattern = in_.1;
+            let body = in_.2.unwrap_or_default();
+            let tail = in_.3;
+            let linecol = in_.4;
+            ps.on_line(linecol.0);
+            ps.emit_indent();
+            ps.emit_in_keyword();
+
+            ps.with_start_of_line(false, |ps| {
+                ps.emit_space();
+                format_guarded_pattern(ps, pattern);
+            });
+
+            ps.emit_newline();
+            ps.new_block(|ps| {
+                ps.with_start_of_line(true, |ps| {
+                    for expr in body {
+                        format_expression(ps, expr);
+                    }
+                });
+            });
+
+            if let Some(tail) = tail {
+                format_in_or_else(ps, *tail);
+            }
+        }
+        InOrElse::Else(e) => format_when_or_else(ps, WhenOrElse::Else(e)),
+    }
+}
+
+pub fn format_guarded_pattern(ps: &mut ParserState, pattern: GuardedPattern) {
+    let (pattern, guard) = match pattern {
+        GuardedPattern::If(guard) => (guard.2, Some(("if", guard.1))),
+        GuardedPattern::Unless(guard) => (guard.2, Some(("unless", guard.1))),
+        GuardedPattern::Unguarded(pattern) => (pattern, None),
+    };
+
+    format_pattern(ps, pattern);
+    if let Some((kw, cond)) = guard {
+        ps.emit_mod_keyword(format!(" {} ", kw));
+        format_expression(ps, *cond);
+    }
+}
+
+// the parts of an array, find or hash pattern, which are all laid out like
+// the elements of a literal, but never get a trailing comma: `[a, b,]`
+// would match arrays of any length
+enum PatternElement {
+    Pattern(Pattern),
+    Rest(PatternRest),
+    Pair(Label, Option<Pattern>),
+    KwRest(KwRestPattern),
+}
+
+fn format_pattern_elements(
+    ps: &mut ParserState,
+    constant: Option<Box<Expression>>,
+    delims: BreakableDelims,
+    elements: Vec<PatternElement>,
+) {
+    if let Some(constant) = constant {
+        format_expression(ps, *constant);
+    }
+
+    ps.breakable_of(delims, |ps| {
+        let len = elements.len();
+        for (idx, element) in elements.into_iter().enumerate() {
+            ps.emit_soft_indent();
+            ps.with_start_of_line(false, |ps| match element {
+                PatternElement::Pattern(pattern) => format_pattern(ps, pattern),
+                PatternElement::Rest(rest) => format_pattern_rest(ps, "*", rest),
+                PatternElement::Pair(label, value) => {
+                    handle_string_and_linecol(ps, label.1, label.2);
+                    if let Some(value) = value {
+                        ps.emit_space();
+                        format_pattern(ps, value);
+                    }
+                }
+                PatternElement::KwRest(KwRestPattern::Rest(rest)) => {
+                    format_pattern_rest(ps, "**", rest)
+                }
+                PatternElement::KwRest(KwRestPattern::NoRest(_)) => {
+                    ps.emit_ident("**nil".to_string())
+                }
+            });
+            if idx != len - 1 {
+                ps.emit_comma();
+            }
+            ps.emit_soft_newline();
+        }
+    });
+}
+
+// `Const(...)` and `Const[...]` match the same way, so keep whichever the
+// source used
+fn constant_pattern_delims(delimiter: Option<String>) -> BreakableDelims {
+    match delimiter.as_deref() {
+        Some("[") => BreakableDelims::for_array(),
+        _ => BreakableDelims::for_method_call(),
+    }
+}
+
+fn format_pattern_rest(ps: &mut ParserState, splat: &str, rest: PatternRest) {
+    ps.emit_ident(splat.to_string());
+    if let Some(ident) = rest.1 {
+        format_ident(ps, ident);
+    }
+}
+
+pub fn format_pattern(ps: &mut ParserState, pattern: Pattern) {
+    match pattern {
+        Pattern::AryPtn(aryptn) => {
+            let (constant, pre, rest, post, delimiter) =
+                (aryptn.1, aryptn.2, aryptn.3, aryptn.4, aryptn.5);
+            let mut elements: Vec<PatternElement> = pre
+                .unwrap_or_default()
+                .into_iter()
+                .map(PatternElement::Pattern)
+                .collect();
+            elements.extend(rest.map(PatternElement::Rest));
+            elements.extend(
+                post.unwrap_or_default()
+                    .into_iter()
+                    .map(PatternElement::Pattern),
+            );
+
+            if constant.is_none() && elements.is_empty() {
+                ps.emit_ident("[]".to_string());
+                return;
+            }
+            let delims = if constant.is_some() {
+                constant_pattern_delims(delimiter)
+            } else {
+                BreakableDelims::for_array()
+            };
+            format_pattern_elements(ps, constant, delims, elements);
+        }
+        Pattern::FndPtn(fndptn) => {
+            let (constant, pre, patterns, post, delimiter) =
+                (fndptn.1, fndptn.2, fndptn.3, fndptn.4, fndptn.5);
+            let mut elements = vec![PatternElement::Rest(pre)];
+            elements.extend(patterns.into_iter().map(PatternElement::Pattern));
+            elements.push(PatternElement::Rest(post));
+
+            let delims = if constant.is_some() {
+                constant_pattern_delims(delimiter)
+            } else {
+                BreakableDelims::for_array()
+            };
+            format_pattern_elements(ps, constant, delims, elements);
+        }
+        Pattern::HshPtn(hshptn) => {
+            let (constant, pairs, rest, delimiter) = (hshptn.1, hshptn.2, hshptn.3, hshptn.4);
+            let mut elements: Vec<PatternElement> = pairs
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(label, value)| PatternElement::Pair(label, value))
+                .collect();
+            elements.extend(rest.map(PatternElement::KwRest));
+
+            if constant.is_none() && elements.is_empty() {
+                ps.emit_ident("{}".to_string());
+                return;
+            }
+            let delims = if constant.is_some() {
+                constant_pattern_delims(delimiter)
+            } else {
+                BreakableDelims::for_hash()
+            };
+            format_pattern_elements(ps, constant, delims, elements);
+        }
+        Pattern::Binary(binary) => {
+            format_pattern(ps, binary.1);
+            ps.emit_space();
+            ps.emit_ident(binary.2);
+            ps.emit_space();
+            format_pattern(ps, binary.3);
+        }
+        Pattern::PinnedExpression(pinned) => {
+            ps.emit_ident("^(".to_string());
+            format_expression(ps, *pinned.1);
+            ps.emit_ident(")".to_string());
+        }
+        Pattern::VarField(vf) => format_var_field(ps, vf),
+        Pattern::Value(expr) => {
+            let is_pinned_variable = matches!(
+                *expr,
+                Expression::VarRef(VarRef(_, VarRefType::Ident(_)))
+                    | Expression::VarRef(VarRef(_, VarRefType::IVar(_)))
+                    | Expression::VarRef(VarRef(_, VarRefType::CVar(_)))
+                    | Expression::VarRef(VarRef(_, VarRefType::GVar(_)))
+            );
+            if is_pinned_variable {
+                ps.emit_ident("^".to_string());
+            }
+            format_expression(ps, *expr);
+        }
+    }
+}
+
 pub fn format_retry(ps: &mut ParserState, _r: Retry) {
     if ps.at_start_of_line() {
         ps.emit_indent();
@@ -2477,9 +2973,11 @@ pub fn format_opassign(ps: &mut ParserState, opassign: OpAssign) {
         ps.emit_newline();
     }
 }
-pub fn format_to_proc(ps: &mut ParserState, e: Box<Expression>) {
+pub fn format_to_proc(ps: &mut ParserState, e: Option<Box<Expression>>) {
     ps.emit_ident("&".to_string());
-    ps.with_start_of_line(false, |ps| format_expression(ps, *e));
+    if let Some(e) = e {
+        ps.with_start_of_line(false, |ps| format_expression(ps, *e));
+    }
 }
 
 pub fn format_zsuper(ps: &mut ParserState) {
@@ -2494,6 +2992,18 @@ pub fn format_zsuper(ps: &mut ParserState) {
     }
 }
 
+pub fn format_args_forward(ps: &mut ParserState) {
+    if ps.at_start_of_line() {
+        ps.emit_indent();
+    }
+
+    ps.emit_ident("...".to_string());
+
+    if ps.at_start_of_line() {
+        ps.emit_newline();
+    }
+}
+
 pub fn format_yield0(ps: &mut ParserState) {
     if ps.at_start_of_line() {
         ps.emit_indent();
@@ -2567,7 +3077,10 @@ pub fn format_bare_return_args(ps: &mut ParserState, args: ArgsAddStarOrExpressi
 }
 
 pub fn format_expression(ps: &mut ParserState, expression: Expression) {
-    let expression = normalize(expression);
+    ps.format_statement(|ps| format_normalized_expression(ps, normalize(expression)));
+}
+
+fn format_normalized_expression(ps: &mut ParserState, expression: Expression) {
     match expression {
         Expression::Def(def) => format_def(ps, def),
         Expression::MethodCall(mc) => format_method_call(ps, mc),
@@ -2630,8 +3143,10 @@ pub fn format_expression(ps: &mut ParserState, expression: Expression) {
         Expression::OpAssign(op) => format_opassign(ps, op),
         Expression::Unless(u) => format_unless(ps, u),
         Expression::ToProc(ToProc(_, e)) => format_to_proc(ps, e),
+        Expression::RightwardAssign(ra) => format_rightward_assign(ps, ra),
         Expression::ZSuper(..) => format_zsuper(ps),
         Expression::Yield0(..) => format_yield0(ps),
+        Expression::ArgsForward(..) => format_args_forward(ps),
         Expression::Return(ret) => format_return(ps, ret),
         Expression::BeginBlock(begin) => format_begin_block(ps, begin),
         Expression::EndBlock(end) => format_end_block(ps, end),
@@ -2647,4 +3162,5 @@ pub fn format_program(ps: &mut ParserState, program: Program) {
     for expression in program.1 {
         format_expression(ps, expression);
     }
+    ps.flush_trailing_comments();
 }
@@ -0,0 +1,68 @@
+use crate::types::ColNumber;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum QuoteStyle {
+    // always use double quotes, this is what rubyfmt has always done
+    Double,
+    // use single quotes for strings that don't need escapes or interpolation
+    PreferSingle,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TrailingCommas {
+    Never,
+    // add a trailing comma to arrays, hashes and method call arguments when
+    // they're broken over multiple lines
+    Multiline,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LineEndings {
+    // use whichever line ending most of the input's lines end with
+    Preserve,
+    Lf,
+    Crlf,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlankLinePolicy {
+    // keep (collapsed) blank lines the user wrote between statements
+    Preserve,
+    // only keep the blank lines rubyfmt inserts itself
+    Remove,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CommentAlignment {
+    // leave a single space between code and its trailing comment
+    Never,
+    // line up the trailing comments on consecutive lines in to one column
+    Consecutive,
+}
+
+/// Knobs for the formatter. `FormatOptions::default()` produces exactly the
+/// same output as `format_buffer`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FormatOptions {
+    pub line_width: usize,
+    pub indent_width: ColNumber,
+    pub quote_style: QuoteStyle,
+    pub trailing_commas: TrailingCommas,
+    pub blank_lines: BlankLinePolicy,
+    pub line_endings: LineEndings,
+    pub comment_alignment: CommentAlignment,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            line_width: 120,
+            indent_width: 2,
+            quote_style: QuoteStyle::Double,
+            trailing_commas: TrailingCommas::Never,
+            blank_lines: BlankLinePolicy::Preserve,
+            line_endings: LineEndings::Preserve,
+            comment_alignment: CommentAlignment::Never,
+        }
+    }
+}
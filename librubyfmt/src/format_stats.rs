@@ -0,0 +1,71 @@
+use std::fmt;
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Phase {
+    // running ripper over the input buffer
+    Parse,
+    // turning the ripper sexp in to `ripper_tree_types`
+    Deserialize,
+    // walking the tree and building the render queue
+    Format,
+    // laying out breakables and writing the final tokens
+    Render,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct FormatStats {
+    pub parse: Duration,
+    pub deserialize: Duration,
+    pub format: Duration,
+    pub render: Duration,
+}
+
+impl FormatStats {
+    pub fn time<T, F>(&mut self, phase: Phase, f: F) -> T
+    where
+        F: FnOnce() -> T,
+    {
+        let started_at = Instant::now();
+        let res = f();
+        *self.phase_mut(phase) += started_at.elapsed();
+        res
+    }
+
+    pub fn total(&self) -> Duration {
+        self.parse + self.deserialize + self.format + self.render
+    }
+
+    fn phase_mut(&mut self, phase: Phase) -> &mut Duration {
+        match phase {
+            Phase::Parse => &mut self.parse,
+            Phase::Deserialize => &mut self.deserialize,
+            Phase::Format => &mut self.format,
+            Phase::Render => &mut self.render,
+        }
+    }
+}
+
+impl AddAssign for FormatStats {
+    fn add_assign(&mut self, other: FormatStats) {
+        self.parse += other.parse;
+        self.deserialize += other.deserialize;
+        self.format += other.format;
+        self.render += other.render;
+    }
+}
+
+impl fmt::Display for FormatStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "parse: {:.2?}, deserialize: {:.2?}, format: {:.2?}, render: {:.2?}, total: {:.2?}",
+            self.parse,
+            self.deserialize,
+            self.format,
+            self.render,
+            self.total()
+        )
+    }
+}
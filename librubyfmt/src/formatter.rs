@@ -0,0 +1,130 @@
+use crate::file_comments::FileComments;
+use crate::format_options::FormatOptions;
+use crate::generated_files::GeneratedFileMarkers;
+use crate::ripper_tree_types::Program;
+use crate::source_layout::SourceLayout;
+use crate::{
+    check_syntax, format_parsed_program, init_ruby_vm, parse_program, report_for_buffer,
+    report_unless_generated, FormatReport, FormatStats, RichFormatError,
+};
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+use std::thread::{self, ThreadId};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum InitError {
+    // ruby_setup failed, there's no usable VM in this process
+    RubySetupFailed,
+    // the VM came up but we couldn't load ripper in to it
+    RipperLoadFailed,
+    // the VM came up but we couldn't load rubyfmt_lib.rb in to it
+    RubyfmtLoadFailed,
+    // the VM was initialised on a different thread, and it can only be used
+    // from the thread that initialised it
+    WrongThread,
+}
+
+static INIT_RESULT: OnceLock<Result<ThreadId, InitError>> = OnceLock::new();
+
+/// Initialises the Ruby VM the first time it's called, and returns the
+/// outcome of that first call (including which thread it happened on) on
+/// every call after that.
+pub(crate) fn init_once() -> Result<ThreadId, InitError> {
+    *INIT_RESULT.get_or_init(|| init_ruby_vm().map(|()| thread::current().id()))
+}
+
+// Our own VM can only be used from the thread that started it. As an
+// extension we're always called holding the host's GVL, so any ruby thread
+// will do.
+#[cfg(not(feature = "ruby_extension"))]
+fn check_vm_thread(vm_thread: ThreadId) -> Result<(), InitError> {
+    if vm_thread == thread::current().id() {
+        Ok(())
+    } else {
+        Err(InitError::WrongThread)
+    }
+}
+
+#[cfg(feature = "ruby_extension")]
+fn check_vm_thread(_vm_thread: ThreadId) -> Result<(), InitError> {
+    Ok(())
+}
+
+/// A handle to the embedded Ruby VM. Creating one initialises the VM if
+/// nothing has yet, so there's no need to call `rubyfmt_init` first. The VM
+/// can only be used from the thread that initialised it, so `Formatter` is
+/// neither `Send` nor `Sync`:
+///
+/// ```compile_fail
+/// fn assert_send<T: Send>() {}
+/// assert_send::<rubyfmt::Formatter>();
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct Formatter {
+    options: FormatOptions,
+    // raw pointers are !Send and !Sync, which pins us to the VM's thread
+    _vm_thread: PhantomData<*const ()>,
+}
+
+/// A parsed buffer, ready to be handed back to `Formatter::format_parsed`.
+/// This doesn't hold any references in to the Ruby VM.
+#[derive(Debug, Clone)]
+pub struct ParsedProgram {
+    pub(crate) program: Program,
+    pub(crate) file_comments: FileComments,
+    pub(crate) end_data: Option<String>,
+    pub(crate) layout: SourceLayout,
+}
+
+impl Formatter {
+    pub fn new() -> Result<Formatter, InitError> {
+        check_vm_thread(init_once()?)?;
+
+        Ok(Formatter {
+            options: FormatOptions::default(),
+            _vm_thread: PhantomData,
+        })
+    }
+
+    pub fn with_options(mut self, options: FormatOptions) -> Formatter {
+        self.options = options;
+        self
+    }
+
+    pub fn options(&self) -> &FormatOptions {
+        &self.options
+    }
+
+    pub fn format(&self, buf: &str) -> Result<String, RichFormatError> {
+        self.format_with_report(buf).map(|report| report.output)
+    }
+
+    /// Formats the passed buffer, see `format_buffer_with_report`
+    pub fn format_with_report(&self, buf: &str) -> Result<FormatReport, RichFormatError> {
+        report_for_buffer(buf, &self.options)
+    }
+
+    /// Formats the passed buffer unless it's generated, see
+    /// `format_buffer_unless_generated`
+    pub fn format_unless_generated(
+        &self,
+        buf: &str,
+        markers: &GeneratedFileMarkers,
+    ) -> Result<Option<FormatReport>, RichFormatError> {
+        report_unless_generated(buf, &self.options, markers)
+    }
+
+    /// Checks the passed buffer is valid ruby, see `syntax_check`
+    pub fn check(&self, buf: &str) -> Result<(), RichFormatError> {
+        check_syntax(buf)
+    }
+
+    pub fn parse(&self, buf: &str) -> Result<ParsedProgram, RichFormatError> {
+        parse_program(buf, &mut FormatStats::default())
+    }
+
+    pub fn format_parsed(&self, parsed: ParsedProgram) -> Result<String, RichFormatError> {
+        format_parsed_program(parsed, FormatStats::default(), &self.options)
+            .map(|report| report.output)
+    }
+}
@@ -0,0 +1,226 @@
+use crate::format_options::FormatOptions;
+use crate::formatter::{Formatter, InitError};
+use crate::panic_message;
+use crate::RichFormatError;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+type Job = Box<dyn FnOnce(&Formatter) + Send>;
+
+/// Why a `FormatHandle` has no result for its request
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServiceError {
+    /// the VM thread went away before it got round to the request
+    Stopped,
+    /// rubyfmt panicked while running the request, with this message. This
+    /// is a bug, but only that request failed, and the service carries on.
+    Panicked(String),
+}
+
+// The VM thread, once something has started it. It runs until the process
+// exits, as the VM can't be started again on another one.
+static VM_THREAD: OnceLock<Result<Mutex<Sender<Job>>, InitError>> = OnceLock::new();
+
+fn start_vm_thread() -> Result<Mutex<Sender<Job>>, InitError> {
+    let (jobs, receiver) = mpsc::channel::<Job>();
+    let (init_sender, init_receiver) = mpsc::channel();
+
+    thread::Builder::new()
+        .name("rubyfmt-vm".to_string())
+        .spawn(move || {
+            let formatter = match Formatter::new() {
+                Ok(formatter) => {
+                    let _ = init_sender.send(Ok(()));
+                    formatter
+                }
+                Err(e) => {
+                    let _ = init_sender.send(Err(e));
+                    return;
+                }
+            };
+
+            for job in receiver {
+                job(&formatter);
+            }
+        })
+        .expect("spawning the rubyfmt VM thread");
+
+    init_receiver
+        .recv()
+        .expect("the VM thread always reports how init went")?;
+    Ok(Mutex::new(jobs))
+}
+
+/// Formats buffers on a dedicated thread that owns the Ruby VM, so that any
+/// thread can submit work. Every service, and every clone of one, shares the
+/// same VM thread, which lives until the process exits, so services can be
+/// created and dropped as often as you like.
+///
+/// The VM can only be initialised once per process, so the first service has
+/// to be the first thing to touch it: `FormatterService::new()` returns
+/// `InitError::WrongThread` if a `Formatter` was already created elsewhere.
+/// If starting the VM failed, every later call returns the same error.
+#[derive(Debug, Copy, Clone)]
+pub struct FormatterService {
+    jobs: &'static Mutex<Sender<Job>>,
+    options: FormatOptions,
+}
+
+impl FormatterService {
+    pub fn new() -> Result<FormatterService, InitError> {
+        let jobs = VM_THREAD
+            .get_or_init(start_vm_thread)
+            .as_ref()
+            .map_err(|e| *e)?;
+
+        Ok(FormatterService {
+            jobs,
+            options: FormatOptions::default(),
+        })
+    }
+
+    pub fn with_options(mut self, options: FormatOptions) -> FormatterService {
+        self.options = options;
+        self
+    }
+
+    pub fn format(&self, buf: String) -> FormatHandle<Result<String, RichFormatError>> {
+        self.format_with_options(buf, self.options)
+    }
+
+    pub fn format_with_options(
+        &self,
+        buf: String,
+        options: FormatOptions,
+    ) -> FormatHandle<Result<String, RichFormatError>> {
+        self.run(move |formatter| formatter.with_options(options).format(&buf))
+    }
+
+    pub fn check(&self, buf: String) -> FormatHandle<Result<(), RichFormatError>> {
+        self.run(move |formatter| formatter.check(&buf))
+    }
+
+    /// Runs `f` on the VM thread with the service's `Formatter`, for anything
+    /// `format` and `check` don't cover, like parsing a buffer and formatting
+    /// the result with `Formatter::format_parsed`. If `f` panics, the handle
+    /// returns `ServiceError::Panicked`.
+    pub fn run<T, F>(&self, f: F) -> FormatHandle<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Formatter) -> T + Send + 'static,
+    {
+        let handle = FormatHandle::new();
+        let mut completer = Completer {
+            shared: handle.shared.clone(),
+            completed: false,
+        };
+        let options = self.options;
+        let job: Job = Box::new(move |formatter| {
+            let formatter = formatter.with_options(options);
+            match panic::catch_unwind(AssertUnwindSafe(|| f(&formatter))) {
+                Ok(value) => completer.complete(value),
+                Err(payload) => {
+                    completer.resolve(Err(ServiceError::Panicked(panic_message(&*payload))))
+                }
+            }
+        });
+
+        // if the VM thread is gone the job gets dropped here, which drops the
+        // completer and resolves the handle with `ServiceError::Stopped`
+        let _ = self
+            .jobs
+            .lock()
+            .expect("no one panics while holding the jobs lock")
+            .send(job);
+        handle
+    }
+}
+
+struct Shared<T> {
+    result: Option<Result<T, ServiceError>>,
+    waker: Option<Waker>,
+}
+
+/// The result of a request to a `FormatterService`. Either block on it with
+/// `wait`, or `.await` it.
+pub struct FormatHandle<T> {
+    shared: Arc<(Mutex<Shared<T>>, Condvar)>,
+}
+
+impl<T> FormatHandle<T> {
+    fn new() -> Self {
+        FormatHandle {
+            shared: Arc::new((
+                Mutex::new(Shared {
+                    result: None,
+                    waker: None,
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    pub fn wait(self) -> Result<T, ServiceError> {
+        let (lock, condvar) = &*self.shared;
+        let mut shared = lock.lock().expect("handle lock poisoned");
+        loop {
+            if let Some(result) = shared.result.take() {
+                return result;
+            }
+            shared = condvar.wait(shared).expect("handle lock poisoned");
+        }
+    }
+}
+
+impl<T> Future for FormatHandle<T> {
+    type Output = Result<T, ServiceError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.0.lock().expect("handle lock poisoned");
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// Lives on the VM thread and fills in a `FormatHandle`. Dropping it without
+// completing resolves the handle with `ServiceError::Stopped`, so waiters
+// never hang.
+struct Completer<T> {
+    shared: Arc<(Mutex<Shared<T>>, Condvar)>,
+    completed: bool,
+}
+
+impl<T> Completer<T> {
+    fn complete(mut self, value: T) {
+        self.resolve(Ok(value));
+    }
+
+    fn resolve(&mut self, result: Result<T, ServiceError>) {
+        self.completed = true;
+        let (lock, condvar) = &*self.shared;
+        let mut shared = lock.lock().expect("handle lock poisoned");
+        shared.result = Some(result);
+        if let Some(waker) = shared.waker.take() {
+            waker.wake();
+        }
+        condvar.notify_all();
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.resolve(Err(ServiceError::Stopped));
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use crate::file_comments::FileComments;
+
+/// Configures how we spot generated files, which are regenerated constantly
+/// and so shouldn't be formatted. A file is considered generated if any of
+/// `markers` appears (case insensitively) in the first `max_comment_lines`
+/// lines of the comment block at the very start of the file.
+#[derive(Debug, Clone)]
+pub struct GeneratedFileMarkers {
+    pub markers: Vec<String>,
+    pub max_comment_lines: usize,
+}
+
+impl Default for GeneratedFileMarkers {
+    fn default() -> Self {
+        GeneratedFileMarkers {
+            markers: vec![
+                "auto-generated".to_string(),
+                "automatically generated".to_string(),
+                "do not edit".to_string(),
+                "@generated".to_string(),
+            ],
+            max_comment_lines: 5,
+        }
+    }
+}
+
+impl GeneratedFileMarkers {
+    pub fn matches(&self, file_comments: &FileComments) -> bool {
+        let markers = self
+            .markers
+            .iter()
+            .map(|m| m.to_lowercase())
+            .collect::<Vec<_>>();
+
+        file_comments
+            .start_of_file_comment_lines()
+            .take(self.max_comment_lines)
+            .map(|line| line.to_lowercase())
+            .any(|line| markers.iter().any(|m| line.contains(m.as_str())))
+    }
+}
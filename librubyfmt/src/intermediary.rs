@@ -34,17 +34,19 @@ impl Intermediary {
         self.tokens.len()
     }
 
+    // trailing comments are skipped, so that `end # comment` is treated the
+    // same as a bare `end`
     pub fn last_4(&self) -> Option<(&LineToken, &LineToken, &LineToken, &LineToken)> {
-        if self.len() < 4 {
-            return None;
-        }
-
-        Some((
-            &self.tokens[self.len() - 4],
-            &self.tokens[self.len() - 3],
-            &self.tokens[self.len() - 2],
-            &self.tokens[self.len() - 1],
-        ))
+        let mut tokens = self
+            .tokens
+            .iter()
+            .rev()
+            .filter(|t| !t.is_trailing_comment());
+        let last = tokens.next()?;
+        let third = tokens.next()?;
+        let second = tokens.next()?;
+        let first = tokens.next()?;
+        Some((first, second, third, last))
     }
 
     pub fn into_tokens(self) -> Vec<LineToken> {
@@ -1,10 +1,11 @@
 #![deny(warnings, missing_copy_implementations)]
 
 use serde::de::value;
+use std::any::Any;
+use std::fmt;
 use std::io::{Cursor, Write};
-use std::slice;
-use std::str;
 
+#[cfg(feature = "jemalloc")]
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
@@ -15,8 +16,16 @@ mod ruby;
 mod comment_block;
 mod de;
 mod delimiters;
+#[cfg(feature = "ffi")]
+mod ffi;
 mod file_comments;
+mod file_io;
 mod format;
+mod format_options;
+mod format_stats;
+mod formatter;
+mod formatter_service;
+mod generated_files;
 mod intermediary;
 mod line_metadata;
 mod line_tokens;
@@ -24,131 +33,238 @@ mod parser_state;
 mod render_queue_writer;
 mod render_targets;
 mod ripper_tree_types;
+#[cfg(feature = "ruby_extension")]
+mod ruby_extension;
 mod ruby_ops;
+mod source_encoding;
+mod source_layout;
 mod types;
 
+#[cfg(feature = "test_hooks")]
+pub use ffi::rubyfmt_test_panic_next_call;
+#[cfg(feature = "ffi")]
+pub use ffi::{
+    rubyfmt_default_options, rubyfmt_format_buffer, rubyfmt_format_buffer_with_options,
+    rubyfmt_init, rubyfmt_is_formatted, rubyfmt_last_error_message, rubyfmt_string_free,
+    rubyfmt_string_len, rubyfmt_string_ptr, rubyfmt_version, FormatError, InitStatus,
+    RubyfmtOptions, RubyfmtString,
+};
 use file_comments::FileComments;
+pub use file_io::{format_file, format_reader, FormatTarget};
+pub use format_options::{
+    BlankLinePolicy, CommentAlignment, FormatOptions, LineEndings, QuoteStyle, TrailingCommas,
+};
+pub use format_stats::{FormatStats, Phase};
+pub use formatter::{Formatter, InitError, ParsedProgram};
+pub use formatter_service::{FormatHandle, FormatterService, ServiceError};
+pub use generated_files::GeneratedFileMarkers;
 use parser_state::ParserState;
+pub use render_queue_writer::LongLine;
+pub use ruby_ops::SyntaxErrorDetail;
 use ruby_ops::{load_rubyfmt, ParseError, Parser, RipperTree};
+pub use source_encoding::SourceEncoding;
+use source_layout::SourceLayout;
+use types::LineNumber;
 
-#[cfg(debug_assertions)]
+#[cfg(all(debug_assertions, feature = "logger"))]
 use log::debug;
-#[cfg(debug_assertions)]
+#[cfg(all(debug_assertions, feature = "logger"))]
 use simplelog::{Config, LevelFilter, TermLogger, TerminalMode};
 
 extern "C" {
     pub fn Init_ripper();
 }
 
-pub struct RubyfmtString(Box<str>);
-
-#[derive(Debug, Copy, Clone)]
-pub enum InitStatus {
-    OK = 0,
-    ERROR = 1,
-}
+/// The version of ruby whose grammar rubyfmt parses, picked at build time
+/// with the `ruby_2_6`, `ruby_2_7` or `ruby_3_2` cargo features. Syntax added
+/// in later rubies is a syntax error.
+pub const RUBY_VERSION: &str = env!("RUBYFMT_RUBY_VERSION");
 
 #[derive(Debug)]
 pub enum RichFormatError {
-    SyntaxError,
+    SyntaxError(Vec<SyntaxErrorDetail>),
     RipperParseFailure(value::Error),
     IOError(std::io::Error),
     OtherRubyError(String),
+    InvalidUtf8(std::str::Utf8Error),
+    InvalidEncoding(String),
+    InitFailed(InitError),
+}
+
+impl From<InitError> for RichFormatError {
+    fn from(e: InitError) -> Self {
+        RichFormatError::InitFailed(e)
+    }
 }
 
-impl RichFormatError {
-    fn into_format_error(self) -> FormatError {
+impl fmt::Display for RichFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            RichFormatError::SyntaxError => FormatError::SyntaxError,
-            RichFormatError::RipperParseFailure(_) => FormatError::RipperParseFailure,
-            RichFormatError::IOError(_) => FormatError::IOError,
-            RichFormatError::OtherRubyError(_) => FormatError::OtherRubyError,
+            RichFormatError::SyntaxError(errors) if errors.is_empty() => write!(f, "syntax error"),
+            RichFormatError::SyntaxError(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            RichFormatError::RipperParseFailure(e) => {
+                write!(f, "failed to deserialize the ripper tree: {}", e)
+            }
+            RichFormatError::IOError(e) => write!(f, "IO error: {}", e),
+            RichFormatError::OtherRubyError(s) => write!(f, "ruby error: {}", s),
+            RichFormatError::InvalidUtf8(e) => write!(f, "input is not valid UTF-8: {}", e),
+            RichFormatError::InvalidEncoding(s) => write!(f, "could not transcode input: {}", s),
+            RichFormatError::InitFailed(e) => write!(f, "could not start the ruby VM: {:?}", e),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub enum FormatError {
-    OK = 0,
-    SyntaxError = 1,
-    RipperParseFailure = 2,
-    IOError = 3,
-    OtherRubyError = 4,
+// The message a panic was raised with, for reporting panics we caught
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Everything we learned while formatting a buffer, alongside the output
+#[derive(Debug)]
+pub struct FormatReport {
+    pub output: String,
+    pub stats: FormatStats,
+    pub long_lines: Vec<LongLine>,
 }
 
 pub fn format_buffer(buf: &str) -> Result<String, RichFormatError> {
-    let (tree, file_comments) = run_parser_on(buf)?;
-    let out_data = vec![];
-    let mut output = Cursor::new(out_data);
-    toplevel_format_program(&mut output, tree, file_comments)?;
-    output.flush().expect("flushing to a vec should never fail");
-    Ok(String::from_utf8(output.into_inner()).expect("we never write invalid UTF-8"))
+    format_buffer_with_options(buf, &FormatOptions::default())
 }
 
-#[no_mangle]
-pub extern "C" fn rubyfmt_init() -> libc::c_int {
-    init_logger();
-    let res = ruby_ops::setup_ruby();
-    if res.is_err() {
-        return InitStatus::ERROR as libc::c_int;
-    }
+pub fn format_buffer_with_options(
+    buf: &str,
+    opts: &FormatOptions,
+) -> Result<String, RichFormatError> {
+    format_buffer_with_report(buf, opts).map(|report| report.output)
+}
 
-    let res = unsafe { load_ripper() };
-    if res.is_err() {
-        return InitStatus::ERROR as libc::c_int;
-    }
+/// Formats the passed buffer, also reporting how long each phase of
+/// formatting took
+pub fn format_buffer_with_stats(buf: &str) -> Result<(String, FormatStats), RichFormatError> {
+    format_buffer_with_report(buf, &FormatOptions::default())
+        .map(|report| (report.output, report.stats))
+}
 
-    let res = unsafe { load_rubyfmt() };
-    if res.is_err() {
-        return InitStatus::ERROR as libc::c_int;
-    }
+/// Formats the passed buffer, reporting phase timings and any output lines
+/// that are still too wide because they couldn't be broken
+pub fn format_buffer_with_report(
+    buf: &str,
+    opts: &FormatOptions,
+) -> Result<FormatReport, RichFormatError> {
+    Formatter::new()?
+        .with_options(*opts)
+        .format_with_report(buf)
+}
 
-    InitStatus::OK as libc::c_int
-}
-
-/// # Safety
-/// this function will fail, very badly, if len specifies more bytes than is
-/// available in the passed buffer pointer. It will also fail if the passed
-/// data isn't utf8.
-/// Please don't pass non-utf8 too small buffers.
-#[no_mangle]
-pub unsafe extern "C" fn rubyfmt_format_buffer(
-    ptr: *const u8,
-    len: usize,
-    err: *mut i64,
-) -> *mut RubyfmtString {
-    let input = str::from_utf8_unchecked(slice::from_raw_parts(ptr, len));
-    let output = format_buffer(input);
-    match output {
-        Ok(o) => {
-            *err = FormatError::OK as i64;
-            Box::into_raw(Box::new(RubyfmtString(o.into_boxed_str())))
-        }
-        Err(e) => {
-            *err = e.into_format_error() as i64;
-            std::ptr::null::<RubyfmtString>() as _
-        }
+/// Like `format_buffer_with_report`, but returns `None` without formatting
+/// anything if the buffer starts with a comment containing one of the
+/// generated file markers
+pub fn format_buffer_unless_generated(
+    buf: &str,
+    opts: &FormatOptions,
+    markers: &GeneratedFileMarkers,
+) -> Result<Option<FormatReport>, RichFormatError> {
+    Formatter::new()?
+        .with_options(*opts)
+        .format_unless_generated(buf, markers)
+}
+
+// these expect the VM to be initialised, which the public functions make
+// sure of by going through a `Formatter`
+fn report_for_buffer(buf: &str, opts: &FormatOptions) -> Result<FormatReport, RichFormatError> {
+    let mut stats = FormatStats::default();
+    let parsed = parse_program(buf, &mut stats)?;
+    format_parsed_program(parsed, stats, opts)
+}
+
+fn report_unless_generated(
+    buf: &str,
+    opts: &FormatOptions,
+    markers: &GeneratedFileMarkers,
+) -> Result<Option<FormatReport>, RichFormatError> {
+    let mut stats = FormatStats::default();
+    let (tree, file_comments, end_data, layout) =
+        stats.time(Phase::Parse, || run_parser_on(buf))?;
+    if markers.matches(&file_comments) {
+        return Ok(None);
     }
+    let program = deserialize_program(tree, &mut stats)?;
+    let parsed = ParsedProgram {
+        program,
+        file_comments,
+        end_data,
+        layout,
+    };
+    format_parsed_program(parsed, stats, opts).map(Some)
 }
 
-#[no_mangle]
-pub extern "C" fn rubyfmt_string_ptr(s: &RubyfmtString) -> *const u8 {
-    s.0.as_ptr()
+fn parse_program(buf: &str, stats: &mut FormatStats) -> Result<ParsedProgram, RichFormatError> {
+    let (tree, file_comments, end_data, layout) =
+        stats.time(Phase::Parse, || run_parser_on(buf))?;
+    let program = deserialize_program(tree, stats)?;
+    Ok(ParsedProgram {
+        program,
+        file_comments,
+        end_data,
+        layout,
+    })
 }
 
-#[no_mangle]
-pub extern "C" fn rubyfmt_string_len(s: &RubyfmtString) -> usize {
-    s.0.len()
+fn format_parsed_program(
+    parsed: ParsedProgram,
+    mut stats: FormatStats,
+    opts: &FormatOptions,
+) -> Result<FormatReport, RichFormatError> {
+    let out_data = vec![];
+    let mut output = Cursor::new(out_data);
+    let long_lines = render_program(
+        &mut output,
+        parsed.program,
+        parsed.file_comments,
+        parsed.end_data,
+        &mut stats,
+        opts,
+        parsed.layout.with_options(opts),
+    )?;
+    output.flush().expect("flushing to a vec should never fail");
+    let output = String::from_utf8(output.into_inner()).expect("we never write invalid UTF-8");
+    Ok(FormatReport {
+        output,
+        stats,
+        long_lines,
+    })
 }
 
-#[no_mangle]
-extern "C" fn rubyfmt_string_free(rubyfmt_string: *mut RubyfmtString) {
-    unsafe {
-        Box::from_raw(rubyfmt_string);
-    }
+#[cfg(not(feature = "ruby_extension"))]
+fn init_ruby_vm() -> Result<(), InitError> {
+    init_logger();
+    ruby_ops::setup_ruby().map_err(|()| InitError::RubySetupFailed)?;
+    unsafe { load_ripper() }.map_err(|()| InitError::RipperLoadFailed)?;
+    unsafe { load_rubyfmt() }.map_err(|()| InitError::RubyfmtLoadFailed)?;
+    Ok(())
+}
+
+// As an extension we're loaded in to a VM that's already running, so we
+// use the host's ripper rather than our own
+#[cfg(feature = "ruby_extension")]
+fn init_ruby_vm() -> Result<(), InitError> {
+    init_logger();
+    unsafe { ruby::eval_str("require 'ripper'") }.map_err(|_| InitError::RipperLoadFailed)?;
+    unsafe { load_rubyfmt() }.map_err(|()| InitError::RubyfmtLoadFailed)?;
+    Ok(())
 }
 
 // Safety: This function expects a functioning Ruby VM
+#[cfg(not(feature = "ruby_extension"))]
 unsafe fn load_ripper() -> Result<(), ()> {
     // trick ruby in to thinking ripper is already loaded
     ruby::eval_str(
@@ -167,21 +283,31 @@ unsafe fn load_ripper() -> Result<(), ()> {
     Init_ripper();
 
     //load each ripper program
-    ruby::eval_str(include_str!(
-        "../ruby_checkout/ruby-2.6.6/ext/ripper/lib/ripper.rb"
-    ))?;
-    ruby::eval_str(include_str!(
-        "../ruby_checkout/ruby-2.6.6/ext/ripper/lib/ripper/core.rb"
-    ))?;
-    ruby::eval_str(include_str!(
-        "../ruby_checkout/ruby-2.6.6/ext/ripper/lib/ripper/lexer.rb"
-    ))?;
-    ruby::eval_str(include_str!(
-        "../ruby_checkout/ruby-2.6.6/ext/ripper/lib/ripper/filter.rb"
-    ))?;
-    ruby::eval_str(include_str!(
-        "../ruby_checkout/ruby-2.6.6/ext/ripper/lib/ripper/sexp.rb"
-    ))?;
+    ruby::eval_str(include_str!(concat!(
+        "../ruby_checkout/",
+        env!("RUBYFMT_RUBY_CHECKOUT"),
+        "/ext/ripper/lib/ripper.rb"
+    )))?;
+    ruby::eval_str(include_str!(concat!(
+        "../ruby_checkout/",
+        env!("RUBYFMT_RUBY_CHECKOUT"),
+        "/ext/ripper/lib/ripper/core.rb"
+    )))?;
+    ruby::eval_str(include_str!(concat!(
+        "../ruby_checkout/",
+        env!("RUBYFMT_RUBY_CHECKOUT"),
+        "/ext/ripper/lib/ripper/lexer.rb"
+    )))?;
+    ruby::eval_str(include_str!(concat!(
+        "../ruby_checkout/",
+        env!("RUBYFMT_RUBY_CHECKOUT"),
+        "/ext/ripper/lib/ripper/filter.rb"
+    )))?;
+    ruby::eval_str(include_str!(concat!(
+        "../ruby_checkout/",
+        env!("RUBYFMT_RUBY_CHECKOUT"),
+        "/ext/ripper/lib/ripper/sexp.rb"
+    )))?;
 
     Ok(())
 }
@@ -191,26 +317,102 @@ pub fn toplevel_format_program<W: Write>(
     tree: RipperTree,
     file_comments: FileComments,
 ) -> Result<(), RichFormatError> {
-    let mut ps = ParserState::new(file_comments);
-    let v: ripper_tree_types::Program =
-        de::from_value(tree).map_err(RichFormatError::RipperParseFailure)?;
+    let mut stats = FormatStats::default();
+    let program = deserialize_program(tree, &mut stats)?;
+    render_program(
+        writer,
+        program,
+        file_comments,
+        None,
+        &mut stats,
+        &FormatOptions::default(),
+        SourceLayout::default(),
+    )?;
+    Ok(())
+}
+
+fn deserialize_program(
+    tree: RipperTree,
+    stats: &mut FormatStats,
+) -> Result<ripper_tree_types::Program, RichFormatError> {
+    stats
+        .time(Phase::Deserialize, || de::from_value(tree))
+        .map_err(RichFormatError::RipperParseFailure)
+}
+
+fn render_program<W: Write>(
+    writer: &mut W,
+    v: ripper_tree_types::Program,
+    file_comments: FileComments,
+    end_data: Option<String>,
+    stats: &mut FormatStats,
+    opts: &FormatOptions,
+    layout: SourceLayout,
+) -> Result<Vec<LongLine>, RichFormatError> {
+    let mut ps = ParserState::new(file_comments, *opts);
 
-    format::format_program(&mut ps, v);
+    stats.time(Phase::Format, || format::format_program(&mut ps, v));
 
-    ps.write(writer).map_err(RichFormatError::IOError)?;
+    let long_lines = stats
+        .time(Phase::Render, || ps.write(writer, layout, end_data))
+        .map_err(RichFormatError::IOError)?;
     writer.flush().map_err(RichFormatError::IOError)?;
-    Ok(())
+    Ok(long_lines)
 }
 
-fn run_parser_on(buf: &str) -> Result<(RipperTree, FileComments), RichFormatError> {
-    Parser::new(buf).parse().map_err(|e| match e {
-        ParseError::SyntaxError => RichFormatError::SyntaxError,
-        ParseError::OtherRubyError(s) => RichFormatError::OtherRubyError(s),
-    })
+/// Checks the passed buffer is valid ruby, without formatting it, much like
+/// `ruby -c`. Every error ripper reports is returned in
+/// `RichFormatError::SyntaxError`.
+pub fn syntax_check(buf: &str) -> Result<(), RichFormatError> {
+    Formatter::new()?.check(buf)
+}
+
+fn check_syntax(buf: &str) -> Result<(), RichFormatError> {
+    let (buf, _) = SourceLayout::normalize(buf);
+    let errors = Parser::new(buf)
+        .syntax_check()
+        .map_err(RichFormatError::from)?;
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(RichFormatError::SyntaxError(errors))
+    }
+}
+
+fn run_parser_on(
+    buf: &str,
+) -> Result<(RipperTree, FileComments, Option<String>, SourceLayout), RichFormatError> {
+    let raw_buf = buf;
+    let (buf, layout) = SourceLayout::normalize(buf);
+    let parser = Parser::new(buf);
+    let (tree, mut file_comments, end_line) = parser.parse().map_err(RichFormatError::from)?;
+    let statement_spans = parser.statement_spans().map_err(RichFormatError::from)?;
+    file_comments.protect_statements(&statement_spans, buf);
+    let end_data = end_line.map(|end_line| data_after_end(raw_buf, end_line));
+    Ok((tree, file_comments, end_data, layout))
+}
+
+// Everything after the `__END__` line is data that ripper never looks at, so
+// it's sliced straight from the buffer we were given, keeping whatever bytes
+// and line endings it has
+fn data_after_end(buf: &str, end_line: LineNumber) -> String {
+    match buf.match_indices('\n').nth(end_line as usize - 1) {
+        Some((newline, _)) => buf[newline + 1..].to_string(),
+        None => String::new(),
+    }
+}
+
+impl From<ParseError> for RichFormatError {
+    fn from(e: ParseError) -> Self {
+        match e {
+            ParseError::SyntaxError(errors) => RichFormatError::SyntaxError(errors),
+            ParseError::OtherRubyError(s) => RichFormatError::OtherRubyError(s),
+        }
+    }
 }
 
 fn init_logger() {
-    #[cfg(debug_assertions)]
+    #[cfg(all(debug_assertions, feature = "logger"))]
     {
         TermLogger::init(LevelFilter::Debug, Config::default(), TerminalMode::Stderr)
             .expect("making a term logger");
@@ -31,9 +31,11 @@ pub enum LineToken {
     BreakableEntry(BreakableEntry),
     Op { op: String },
     DoubleQuote,
+    SingleQuote,
     LTStringContent { content: String },
     SingleSlash,
     Comment { contents: String },
+    TrailingComment { contents: String },
     Delim { contents: String },
     End,
 }
@@ -70,6 +72,19 @@ impl LineToken {
         }
     }
 
+    pub fn is_trailing_comment(&self) -> bool {
+        matches!(self, Self::TrailingComment { .. })
+    }
+
+    // the tokens that come between the last token of one line and the first
+    // token of the next, which a trailing comment has to go in front of
+    pub fn separates_lines(&self) -> bool {
+        match self {
+            Self::Indent { .. } | Self::SoftIndent { .. } | Self::Comment { .. } => true,
+            x => x.is_newline(),
+        }
+    }
+
     pub fn is_newline(&self) -> bool {
         match self {
             Self::HardNewLine => true,
@@ -121,14 +136,67 @@ impl LineToken {
                 }),
             Self::Op { op } => op,
             Self::DoubleQuote => "\"".to_string(),
+            Self::SingleQuote => "'".to_string(),
             Self::LTStringContent { content } => content,
             Self::SingleSlash => "\\".to_string(),
             Self::Comment { contents } => format!("{}\n", contents),
+            Self::TrailingComment { contents } => format!(" {}", contents),
             Self::Delim { contents } => contents,
             Self::End => "end".to_string(),
         }
     }
 
+    // like `into_ruby`, but ending the lines rubyfmt writes itself with
+    // `line_ending`. Anything copied from the source, like string contents
+    // and heredoc bodies, keeps the line endings it was written with.
+    pub fn into_ruby_with_line_ending(self, line_ending: &str) -> String {
+        match self {
+            Self::CollapsingNewLine | Self::HardNewLine | Self::SoftNewline => {
+                line_ending.to_string()
+            }
+            Self::Comment { contents } => {
+                let mut s = contents.lines().collect::<Vec<_>>().join(line_ending);
+                s.push_str(line_ending);
+                s
+            }
+            x => x.into_ruby(),
+        }
+    }
+
+    // a human readable name for this kind of token, used when reporting
+    // lines we couldn't fit in to the maximum width
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            Self::CollapsingNewLine | Self::HardNewLine | Self::SoftNewline => "newline",
+            Self::Indent { .. } | Self::SoftIndent { .. } => "indentation",
+            Self::Keyword { .. }
+            | Self::DefKeyword
+            | Self::ClassKeyword
+            | Self::ModuleKeyword
+            | Self::DoKeyword
+            | Self::ModKeyword { .. }
+            | Self::ConditionalKeyword { .. }
+            | Self::End => "keyword",
+            Self::DirectPart { .. } => "identifier or literal",
+            Self::CommaSpace | Self::Comma | Self::Space => "separator",
+            Self::Dot | Self::ColonColon | Self::LonelyOperator => "method chain",
+            Self::OpenSquareBracket
+            | Self::CloseSquareBracket
+            | Self::OpenCurlyBracket
+            | Self::CloseCurlyBracket
+            | Self::OpenParen
+            | Self::CloseParen
+            | Self::Delim { .. } => "delimiter",
+            Self::BreakableEntry(_) => "breakable",
+            Self::Op { .. } => "operator",
+            Self::DoubleQuote
+            | Self::SingleQuote
+            | Self::LTStringContent { .. }
+            | Self::SingleSlash => "string literal",
+            Self::Comment { .. } | Self::TrailingComment { .. } => "comment",
+        }
+    }
+
     pub fn is_in_need_of_a_trailing_blankline(&self) -> bool {
         self.is_conditional_spaced_token() && !self.is_block_closing_token()
     }
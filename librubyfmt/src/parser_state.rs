@@ -1,14 +1,17 @@
 use crate::comment_block::{CommentBlock, Merge};
 use crate::delimiters::BreakableDelims;
-use crate::file_comments::FileComments;
+use crate::file_comments::{Comment, FileComments};
 use crate::format::{format_inner_string, StringType};
+use crate::format_options::{BlankLinePolicy, FormatOptions};
 use crate::line_tokens::*;
-use crate::render_queue_writer::RenderQueueWriter;
+use crate::render_queue_writer::{LongLine, RenderQueueWriter};
 use crate::render_targets::{BaseQueue, BreakableEntry, ConvertType, LineTokenTarget};
 use crate::ripper_tree_types::StringContentPart;
+use crate::source_layout::SourceLayout;
 use crate::types::{ColNumber, LineNumber};
 use log::debug;
 use std::io::{self, Cursor, Write};
+use std::ops::{Range, RangeInclusive};
 use std::str;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -60,6 +63,20 @@ impl HeredocString {
         }
     }
 }
+
+#[derive(Clone, Copy)]
+struct StatementStart {
+    tokens: usize,
+    heredocs: usize,
+}
+
+struct ProtectedStatement {
+    first_line: LineNumber,
+    // `None` if we already wrote it out along with an earlier statement on
+    // the same line, like `a = 1; b = 2`
+    lines: Option<RangeInclusive<LineNumber>>,
+}
+
 pub struct ParserState {
     depth_stack: Vec<IndentDepth>,
     start_of_line: Vec<bool>,
@@ -70,14 +87,26 @@ pub struct ParserState {
     heredoc_strings: Vec<HeredocString>,
     comments_to_insert: Option<CommentBlock>,
     breakable_entry_stack: Vec<BreakableEntry>,
+    // for the render queue and then each breakable entry, whether `on_line`
+    // has been called since a newline was last pushed to it
+    on_line_since_newline: Vec<bool>,
     formatting_context: Vec<FormattingContext>,
     absorbing_indents: i32,
     insert_user_newlines: bool,
     spaces_after_last_newline: ColNumber,
+    options: FormatOptions,
+    // where the statement we're formatting began in the render queue, until
+    // `on_line` tells us which line it starts on
+    statement_start: Option<StatementStart>,
+    // the protected statement we're formatting, whose tokens we throw away
+    protected_statement: Option<ProtectedStatement>,
+    // the last line of source we've written out verbatim for a protected
+    // statement
+    protected_through: LineNumber,
 }
 
 impl ParserState {
-    pub fn new(fc: FileComments) -> Self {
+    pub fn new(fc: FileComments, options: FormatOptions) -> Self {
         ParserState {
             depth_stack: vec![IndentDepth::new()],
             start_of_line: vec![true],
@@ -88,13 +117,22 @@ impl ParserState {
             heredoc_strings: vec![],
             comments_to_insert: None,
             breakable_entry_stack: vec![],
+            on_line_since_newline: vec![false],
             formatting_context: vec![FormattingContext::Main],
             absorbing_indents: 0,
             insert_user_newlines: true,
             spaces_after_last_newline: 0,
+            options,
+            statement_start: None,
+            protected_statement: None,
+            protected_through: 0,
         }
     }
 
+    pub fn options(&self) -> &FormatOptions {
+        &self.options
+    }
+
     fn consume_to_render_queue(self) -> Vec<LineToken> {
         // ct is arbitrary here
         self.render_queue.into_tokens(ConvertType::SingleLine)
@@ -108,6 +146,20 @@ impl ParserState {
     }
 
     pub fn on_line(&mut self, line_number: LineNumber) {
+        if line_number >= self.current_orig_line_number {
+            if let Some(start) = self.statement_start.take() {
+                self.start_statement(start, line_number);
+            }
+        }
+        if self.protected_statement.is_some() {
+            return;
+        }
+
+        let line_ended = !self.current_on_line_since_newline();
+        for seen in self.on_line_since_newline.iter_mut() {
+            *seen = true;
+        }
+
         if line_number < self.current_orig_line_number {
             return;
         }
@@ -117,10 +169,58 @@ impl ParserState {
             be.push_line_number(line_number);
         }
 
+        let trailing_comments = self
+            .comments_hash
+            .extract_trailing_comments_before_line(line_number);
+        self.attach_trailing_comments(trailing_comments, line_ended);
+
         let comments = self.comments_hash.extract_comments_to_line(line_number);
         self.push_comments(line_number, comments);
     }
 
+    fn current_on_line_since_newline(&self) -> bool {
+        *self
+            .on_line_since_newline
+            .last()
+            .expect("on_line_since_newline is never empty")
+    }
+
+    /// Puts trailing comments back at the end of the line they were on. If
+    /// a newline has been emitted since the last `on_line`, anything after
+    /// it was emitted for the next line, like the opening quote of a string,
+    /// so they go before that newline. Otherwise they go after the last
+    /// thing we emitted, ahead of any indentation or own line comments.
+    fn attach_trailing_comments(&mut self, comments: Vec<Comment>, line_ended: bool) {
+        if comments.is_empty() {
+            return;
+        }
+
+        let mut tokens = comments
+            .into_iter()
+            .map(|c| LineToken::TrailingComment {
+                contents: c.contents,
+            })
+            .collect();
+        let target = self.current_target();
+        let idx = if line_ended {
+            target.index_before_last_newline()
+        } else {
+            None
+        }
+        .unwrap_or_else(|| target.index_after_last_line_content());
+        self.current_target_mut().insert_at(idx, &mut tokens);
+    }
+
+    /// Attaches the trailing comments on the last lines of the file, which
+    /// never have a later line to flush them
+    pub fn flush_trailing_comments(&mut self) {
+        let comments = self
+            .comments_hash
+            .extract_trailing_comments_before_line(LineNumber::MAX);
+        let line_ended = !self.current_on_line_since_newline();
+        self.attach_trailing_comments(comments, line_ended);
+    }
+
     fn push_comments(&mut self, line_number: LineNumber, comments: Option<CommentBlock>) {
         match comments {
             None => {}
@@ -137,7 +237,10 @@ impl ParserState {
             }
         }
 
-        if line_number - self.current_orig_line_number >= 2 && self.insert_user_newlines {
+        if line_number - self.current_orig_line_number >= 2
+            && self.insert_user_newlines
+            && self.options.blank_lines == BlankLinePolicy::Preserve
+        {
             self.insert_extra_newline_at_last_newline();
         }
 
@@ -174,16 +277,21 @@ impl ParserState {
         self.push_token(LineToken::DoubleQuote);
     }
 
+    pub fn emit_single_quote(&mut self) {
+        self.push_token(LineToken::SingleQuote);
+    }
+
     pub fn emit_string_content(&mut self, s: String) {
         self.push_token(LineToken::LTStringContent { content: s });
     }
 
     fn current_spaces(&self) -> ColNumber {
-        2 * self
-            .depth_stack
-            .last()
-            .expect("depth stack is never empty")
-            .get()
+        self.options.indent_width
+            * self
+                .depth_stack
+                .last()
+                .expect("depth stack is never empty")
+                .get()
     }
 
     pub fn emit_ident(&mut self, ident: String) {
@@ -218,6 +326,12 @@ impl ParserState {
         });
     }
 
+    pub fn emit_in_keyword(&mut self) {
+        self.push_token(LineToken::Keyword {
+            keyword: "in".to_string(),
+        });
+    }
+
     pub fn emit_do_keyword(&mut self) {
         self.push_token(LineToken::DoKeyword);
     }
@@ -314,6 +428,9 @@ impl ParserState {
     }
 
     pub fn shift_comments(&mut self) {
+        if self.protected_statement.is_some() {
+            return;
+        }
         let idx_of_prev_hard_newline = self.index_of_prev_hard_newline();
 
         if let Some(new_comments) = self.comments_to_insert.take() {
@@ -327,6 +444,16 @@ impl ParserState {
         }
     }
 
+    /// Brings in the comments from the lines before `line_number` at the
+    /// start of the current line, leaving out the blank lines around them,
+    /// which a chain of calls broken over several lines can't have
+    pub fn shift_comments_before_line(&mut self, line_number: LineNumber) {
+        self.insert_user_newlines = false;
+        self.on_line(line_number);
+        self.insert_user_newlines = true;
+        self.shift_comments();
+    }
+
     pub fn index_of_prev_hard_newline(&self) -> Option<usize> {
         self.current_target().index_of_prev_hard_newline()
     }
@@ -355,7 +482,11 @@ impl ParserState {
         self.push_token(LineToken::LonelyOperator);
     }
 
-    pub fn magic_handle_comments_for_mulitiline_arrays<F>(&mut self, f: F)
+    /// Runs `f` to format the elements of a breakable, then brings in the
+    /// comments on the line after its last element, so that comments before
+    /// a closing delimiter on its own line end up inside the breakable rather
+    /// than after it
+    pub fn handle_comments_for_multiline_breakable<F>(&mut self, f: F)
     where
         F: FnOnce(&mut ParserState),
     {
@@ -447,12 +578,24 @@ impl ParserState {
     }
 
     pub fn new_with_depth_stack_from(ps: &ParserState) -> Self {
-        let mut next_ps = ParserState::new(FileComments::default());
+        let mut next_ps = ParserState::new(FileComments::default(), ps.options);
         next_ps.depth_stack = ps.depth_stack.clone();
         next_ps.current_orig_line_number = ps.current_orig_line_number;
         next_ps
     }
 
+    /// A blank state that still has the comments `ps` hasn't emitted, for
+    /// working out where formatting something would bring them in
+    pub fn new_with_comments_from(ps: &ParserState) -> Self {
+        let mut next_ps = ParserState::new_with_depth_stack_from(ps);
+        next_ps.comments_hash = ps.comments_hash.clone();
+        next_ps
+    }
+
+    pub fn has_comments_in_lines(&self, lines: Range<LineNumber>) -> bool {
+        self.comments_hash.has_comments_in_lines(lines)
+    }
+
     pub fn render_with_blank_state<F>(ps: &mut ParserState, f: F) -> ParserState
     where
         F: FnOnce(&mut ParserState),
@@ -483,6 +626,68 @@ impl ParserState {
             .push(HeredocString::new(symbol, is_squiggly, data));
     }
 
+    /// Formats a statement with `f`, unless a `# rubyfmt:` directive
+    /// protects it, in which case its source is written out verbatim
+    /// instead. We only know which line a statement starts on once `f` calls
+    /// `on_line`, so that's where `start_statement` decides, and anything
+    /// `f` emits for a protected statement gets thrown away.
+    pub fn format_statement<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut ParserState),
+    {
+        // the enclosing statement is protected, so this one is too
+        if self.protected_statement.is_some() {
+            return;
+        }
+        if !self.comments_hash.has_protected_statements()
+            || !self.at_start_of_line()
+            || !self.breakable_entry_stack.is_empty()
+        {
+            f(self);
+            return;
+        }
+
+        // comments from earlier lines go in before `f` emits anything, so
+        // everything after `tokens` is this statement's
+        self.shift_comments();
+        self.statement_start = Some(StatementStart {
+            tokens: self.render_queue.len(),
+            heredocs: self.heredoc_strings.len(),
+        });
+        f(self);
+        self.statement_start = None;
+
+        if let Some(statement) = self.protected_statement.take() {
+            if let Some(lines) = statement.lines {
+                let source = self.comments_hash.take_protected_source(&lines);
+                self.on_line(statement.first_line);
+                self.shift_comments();
+                self.push_token(LineToken::DirectPart { part: source });
+                self.current_orig_line_number = *lines.end();
+                self.protected_through = *lines.end();
+                self.emit_newline();
+            }
+        }
+    }
+
+    fn start_statement(&mut self, start: StatementStart, line_number: LineNumber) {
+        let lines = if line_number <= self.protected_through {
+            None
+        } else {
+            match self.comments_hash.protected_statement_lines(line_number) {
+                Some(lines) => Some(lines),
+                None => return,
+            }
+        };
+
+        self.render_queue.truncate(start.tokens);
+        self.heredoc_strings.truncate(start.heredocs);
+        self.protected_statement = Some(ProtectedStatement {
+            first_line: line_number,
+            lines,
+        });
+    }
+
     pub fn will_render_as_multiline<F>(&mut self, f: F) -> bool
     where
         F: FnOnce(&mut ParserState),
@@ -497,7 +702,8 @@ impl ParserState {
 
     fn render_to_buffer(self) -> Vec<u8> {
         let mut bufio = Cursor::new(Vec::new());
-        self.write(&mut bufio).expect("in memory io cannot fail");
+        self.write(&mut bufio, SourceLayout::default(), None)
+            .expect("in memory io cannot fail");
         bufio.set_position(0);
         bufio.into_inner()
     }
@@ -514,8 +720,13 @@ impl ParserState {
                 next_heredoc.buf.pop();
             };
 
+            // the body's last line ending, which is `\r\n` if that's how
+            // it was written
             if let Some(b'\n') = next_heredoc.buf.last() {
                 next_heredoc.buf.pop();
+                if let Some(b'\r') = next_heredoc.buf.last() {
+                    next_heredoc.buf.pop();
+                }
             };
 
             self.with_surpress_comments(true, |ps| {
@@ -548,6 +759,7 @@ impl ParserState {
         let mut be = BreakableEntry::new(self.current_spaces(), delims);
         be.push_line_number(self.current_orig_line_number);
         self.breakable_entry_stack.push(be);
+        self.on_line_since_newline.push(false);
 
         self.new_block(|ps| {
             ps.emit_collapsing_newline();
@@ -556,6 +768,7 @@ impl ParserState {
 
         self.emit_soft_indent();
 
+        self.on_line_since_newline.pop();
         let insert_be = self
             .breakable_entry_stack
             .pop()
@@ -591,12 +804,35 @@ impl ParserState {
         self.push_token(LineToken::CloseParen);
     }
 
-    pub fn write<W: Write>(self, writer: &mut W) -> io::Result<()> {
-        let rqw = RenderQueueWriter::new(self.consume_to_render_queue());
-        rqw.write(writer)
+    pub fn write<W: Write>(
+        self,
+        writer: &mut W,
+        layout: SourceLayout,
+        end_data: Option<String>,
+    ) -> io::Result<Vec<LongLine>> {
+        let options = self.options;
+        let rqw = RenderQueueWriter::new(self.consume_to_render_queue(), options, layout);
+        let long_lines = rqw.write(writer)?;
+        // the `__END__` line is ours to end, but the data after it goes out
+        // exactly as it came in
+        if let Some(end_data) = end_data {
+            writer.write_all(b"__END__")?;
+            writer.write_all(layout.line_ending.as_str().as_bytes())?;
+            writer.write_all(end_data.as_bytes())?;
+        }
+        Ok(long_lines)
     }
 
     pub fn push_token(&mut self, t: LineToken) {
+        if self.protected_statement.is_some() {
+            return;
+        }
+        if t.is_newline() {
+            *self
+                .on_line_since_newline
+                .last_mut()
+                .expect("on_line_since_newline is never empty") = false;
+        }
         self.current_target_mut().push(t);
     }
 
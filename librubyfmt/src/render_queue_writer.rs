@@ -1,23 +1,101 @@
+use crate::format_options::{CommentAlignment, FormatOptions};
 use crate::intermediary::{BlanklineReason, Intermediary};
 use crate::line_tokens::*;
 use crate::render_targets::{BreakableEntry, ConvertType, LineTokenTarget};
+use crate::source_layout::{SourceLayout, BYTE_ORDER_MARK};
 #[cfg(debug_assertions)]
 use log::debug;
 use std::env;
 use std::io::{self, Write};
 
-const MAX_LINE_LENGTH: usize = 120;
+/// A line of formatted output that is still wider than the configured line
+/// width, because the widest thing on it is something we don't know how to
+/// break
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LongLine {
+    pub line_number: usize,
+    pub width: usize,
+    pub max_width: usize,
+    pub culprit: &'static str,
+}
+
+struct LongLineTracker {
+    max_width: usize,
+    long_lines: Vec<LongLine>,
+    line_number: usize,
+    width: usize,
+    widest_token: Option<(&'static str, usize)>,
+}
+
+impl LongLineTracker {
+    fn new(max_width: usize) -> Self {
+        LongLineTracker {
+            max_width,
+            long_lines: vec![],
+            line_number: 1,
+            width: 0,
+            widest_token: None,
+        }
+    }
+
+    fn observe(&mut self, kind: &'static str, rendered: &str) {
+        for (idx, segment) in rendered.split('\n').enumerate() {
+            if idx != 0 {
+                self.end_line();
+            }
+            let segment_width = segment.trim_end_matches('\r').chars().count();
+            self.width += segment_width;
+            match self.widest_token {
+                Some((_, widest)) if widest >= segment_width => {}
+                _ => self.widest_token = Some((kind, segment_width)),
+            }
+        }
+    }
+
+    fn end_line(&mut self) {
+        if self.width > self.max_width {
+            self.long_lines.push(LongLine {
+                line_number: self.line_number,
+                width: self.width,
+                max_width: self.max_width,
+                culprit: self.widest_token.map(|(kind, _)| kind).unwrap_or("unknown"),
+            });
+        }
+        self.line_number += 1;
+        self.width = 0;
+        self.widest_token = None;
+    }
+
+    fn into_long_lines(mut self) -> Vec<LongLine> {
+        self.end_line();
+        self.long_lines
+    }
+}
+
+// the first trailing comment on a line of output
+struct TrailingCommentLine {
+    line_number: usize,
+    token_index: usize,
+    code_width: usize,
+    line_width: usize,
+}
 
 pub struct RenderQueueWriter {
     tokens: Vec<LineToken>,
+    options: FormatOptions,
+    layout: SourceLayout,
 }
 
 impl RenderQueueWriter {
-    pub fn new(tokens: Vec<LineToken>) -> Self {
-        RenderQueueWriter { tokens }
+    pub fn new(tokens: Vec<LineToken>, options: FormatOptions, layout: SourceLayout) -> Self {
+        RenderQueueWriter {
+            tokens,
+            options,
+            layout,
+        }
     }
 
-    pub fn write<W: Write>(self, writer: &mut W) -> io::Result<()> {
+    pub fn write<W: Write>(self, writer: &mut W) -> io::Result<Vec<LongLine>> {
         let mut accum = Intermediary::new();
         let key = "RUBYFMT_DISABLE_SZUSZ";
         let run = match env::var(key) {
@@ -35,39 +113,42 @@ impl RenderQueueWriter {
                     .into_iter()
                     .map(|t| t.into_multi_line())
                     .collect(),
+                &self.options,
             );
-            Self::write_final_tokens(writer, accum.into_tokens())
+            Self::write_final_tokens(writer, accum.into_tokens(), &self.options, self.layout)
         } else {
-            Self::write_final_tokens(writer, self.tokens)
+            Self::write_final_tokens(writer, self.tokens, &self.options, self.layout)
         }
     }
 
-    fn render_as(accum: &mut Intermediary, tokens: Vec<LineToken>) {
+    fn render_as(accum: &mut Intermediary, tokens: Vec<LineToken>, options: &FormatOptions) {
         for next_token in tokens.into_iter() {
             match next_token {
-                LineToken::BreakableEntry(be) => Self::format_breakable_entry(accum, be),
+                LineToken::BreakableEntry(be) => Self::format_breakable_entry(accum, be, options),
                 x => accum.push(x),
             }
 
-            if accum.len() >= 4 {
-                if let (&LineToken::End, &LineToken::HardNewLine, &LineToken::Indent { .. }, x) =
-                    accum.last_4().expect("we checked length")
-                {
-                    if x.is_in_need_of_a_trailing_blankline() {
-                        accum.insert_trailing_blankline(BlanklineReason::ComesAfterEnd);
-                    }
+            if let Some((&LineToken::End, &LineToken::HardNewLine, &LineToken::Indent { .. }, x)) =
+                accum.last_4()
+            {
+                if x.is_in_need_of_a_trailing_blankline() {
+                    accum.insert_trailing_blankline(BlanklineReason::ComesAfterEnd);
                 }
             }
         }
     }
 
-    fn format_breakable_entry(accum: &mut Intermediary, be: BreakableEntry) {
+    fn format_breakable_entry(
+        accum: &mut Intermediary,
+        be: BreakableEntry,
+        options: &FormatOptions,
+    ) {
         let length = be.single_line_string_length();
 
-        if length > MAX_LINE_LENGTH || be.is_multiline() {
-            Self::render_as(accum, be.into_tokens(ConvertType::MultiLine));
+        if length > options.line_width || be.is_multiline() {
+            Self::render_as(accum, be.into_tokens(ConvertType::MultiLine), options);
         } else {
-            Self::render_as(accum, be.into_tokens(ConvertType::SingleLine));
+            Self::render_as(accum, be.into_tokens(ConvertType::SingleLine), options);
             // after running accum looks like this (or some variant):
             // [.., Comma, Space, DirectPart {part: ""}, <close_delimiter>]
             // so we remove items at positions length-2 until there is nothing
@@ -76,16 +157,144 @@ impl RenderQueueWriter {
         }
     }
 
-    fn write_final_tokens<W: Write>(writer: &mut W, tokens: Vec<LineToken>) -> io::Result<()> {
+    fn write_final_tokens<W: Write>(
+        writer: &mut W,
+        tokens: Vec<LineToken>,
+        options: &FormatOptions,
+        layout: SourceLayout,
+    ) -> io::Result<Vec<LongLine>> {
         #[cfg(debug_assertions)]
         {
             debug!("final tokens: {:?}", tokens);
         }
 
+        if layout.byte_order_mark {
+            write!(writer, "{}", BYTE_ORDER_MARK)?
+        }
+
+        let mut tokens = Self::move_trailing_comments_to_line_ends(tokens);
+        if options.comment_alignment == CommentAlignment::Consecutive {
+            Self::align_trailing_comments(&mut tokens, options.line_width);
+        }
+
+        let line_ending = layout.line_ending.as_str();
+        let mut long_lines = LongLineTracker::new(options.line_width);
         for line_token in tokens.into_iter() {
-            let s = line_token.into_ruby();
+            let kind = line_token.kind_name();
+            let s = line_token.into_ruby_with_line_ending(line_ending);
+            long_lines.observe(kind, &s);
             write!(writer, "{}", s)?
         }
-        Ok(())
+        Ok(long_lines.into_long_lines())
+    }
+
+    // a trailing comment comments out everything after it on its line, so if
+    // anything was rendered after one, e.g. because the line it was on got
+    // joined with the next, we move the comment along to the end of the line
+    fn move_trailing_comments_to_line_ends(tokens: Vec<LineToken>) -> Vec<LineToken> {
+        let mut res = Vec::with_capacity(tokens.len());
+        let mut pending_comments = vec![];
+        for token in tokens.into_iter() {
+            if token.is_trailing_comment() {
+                pending_comments.push(token);
+                continue;
+            }
+            if token.is_newline() {
+                res.append(&mut pending_comments);
+            } else if !pending_comments.is_empty() && !matches!(token, LineToken::Comment { .. }) {
+                // a token like a heredoc body can run on to later lines, in
+                // which case its first line is where the line ends
+                let rendered = token.clone().into_ruby();
+                if let Some(newline) = rendered.find('\n') {
+                    let line_end = if rendered[..newline].ends_with('\r') {
+                        newline - 1
+                    } else {
+                        newline
+                    };
+                    res.push(LineToken::DirectPart {
+                        part: rendered[..line_end].to_string(),
+                    });
+                    res.append(&mut pending_comments);
+                    res.push(LineToken::DirectPart {
+                        part: rendered[line_end..].to_string(),
+                    });
+                    continue;
+                }
+            }
+            res.push(token);
+        }
+        res.append(&mut pending_comments);
+        res
+    }
+
+    // pads the trailing comments on each run of consecutive lines so that
+    // they all start in the same column, leaving the run alone if that would
+    // take any of its lines over the line width
+    fn align_trailing_comments(tokens: &mut [LineToken], max_width: usize) {
+        let mut comment_lines: Vec<TrailingCommentLine> = vec![];
+        let mut line_number = 0;
+        let mut width = 0;
+        for (idx, token) in tokens.iter().enumerate() {
+            if token.is_trailing_comment()
+                && comment_lines.last().map(|c| c.line_number) != Some(line_number)
+            {
+                comment_lines.push(TrailingCommentLine {
+                    line_number,
+                    token_index: idx,
+                    code_width: width,
+                    line_width: 0,
+                });
+            }
+
+            for (segment_idx, segment) in token.clone().into_ruby().split('\n').enumerate() {
+                if segment_idx != 0 {
+                    Self::end_trailing_comment_line(&mut comment_lines, line_number, width);
+                    line_number += 1;
+                    width = 0;
+                }
+                width += segment.chars().count();
+            }
+        }
+        Self::end_trailing_comment_line(&mut comment_lines, line_number, width);
+
+        let mut run_start = 0;
+        for idx in 1..=comment_lines.len() {
+            let run_continues = idx < comment_lines.len()
+                && comment_lines[idx].line_number == comment_lines[idx - 1].line_number + 1;
+            if run_continues {
+                continue;
+            }
+
+            let run = &comment_lines[run_start..idx];
+            run_start = idx;
+            let column = run.iter().map(|c| c.code_width).max().unwrap_or(0);
+            let fits = run
+                .iter()
+                .all(|c| c.line_width + column - c.code_width <= max_width);
+            if run.len() < 2 || !fits {
+                continue;
+            }
+
+            for comment_line in run {
+                if let LineToken::TrailingComment { contents } =
+                    &mut tokens[comment_line.token_index]
+                {
+                    let padding = " ".repeat(column - comment_line.code_width);
+                    contents.insert_str(0, &padding);
+                }
+            }
+        }
+    }
+
+    fn end_trailing_comment_line(
+        comment_lines: &mut [TrailingCommentLine],
+        line_number: usize,
+        width: usize,
+    ) {
+        if let Some(comment_line) = comment_lines.last_mut() {
+            if comment_line.line_number == line_number {
+                comment_line.line_width = width;
+            }
+        }
     }
 }
@@ -21,6 +21,8 @@ pub trait LineTokenTarget {
     fn into_tokens(self, ct: ConvertType) -> Vec<LineToken>;
     fn last_token_is_a_newline(&self) -> bool;
     fn index_of_prev_hard_newline(&self) -> Option<usize>;
+    fn index_after_last_line_content(&self) -> usize;
+    fn index_before_last_newline(&self) -> Option<usize>;
 }
 
 #[derive(Debug, Default, Clone)]
@@ -28,6 +30,16 @@ pub struct BaseQueue {
     tokens: Vec<LineToken>,
 }
 
+impl BaseQueue {
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.tokens.truncate(len)
+    }
+}
+
 impl LineTokenTarget for BaseQueue {
     fn push(&mut self, lt: LineToken) {
         self.tokens.push(lt)
@@ -50,6 +62,25 @@ impl LineTokenTarget for BaseQueue {
             .iter()
             .rposition(|v| v.is_newline() || v.is_comment())
     }
+
+    fn index_after_last_line_content(&self) -> usize {
+        self.tokens
+            .iter()
+            .rposition(|v| !v.separates_lines())
+            .map(|idx| idx + 1)
+            .unwrap_or(0)
+    }
+
+    fn index_before_last_newline(&self) -> Option<usize> {
+        let newline_idx = self.tokens.iter().rposition(|v| v.is_newline())?;
+        Some(
+            self.tokens[..newline_idx]
+                .iter()
+                .rposition(|v| !v.separates_lines())
+                .map(|idx| idx + 1)
+                .unwrap_or(0),
+        )
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -98,6 +129,25 @@ impl LineTokenTarget for BreakableEntry {
             .iter()
             .rposition(|v| v.is_newline() || v.is_comment())
     }
+
+    fn index_after_last_line_content(&self) -> usize {
+        self.tokens
+            .iter()
+            .rposition(|v| !v.separates_lines())
+            .map(|idx| idx + 1)
+            .unwrap_or(0)
+    }
+
+    fn index_before_last_newline(&self) -> Option<usize> {
+        let newline_idx = self.tokens.iter().rposition(|v| v.is_newline())?;
+        Some(
+            self.tokens[..newline_idx]
+                .iter()
+                .rposition(|v| !v.separates_lines())
+                .map(|idx| idx + 1)
+                .unwrap_or(0),
+        )
+    }
 }
 
 impl BreakableEntry {
@@ -122,7 +172,13 @@ impl BreakableEntry {
         self.line_numbers.insert(number);
     }
 
+    // a comment would comment out the rest of a single line breakable, so
+    // one that has any has to be multiline
     pub fn is_multiline(&self) -> bool {
         self.line_numbers.len() > 1
+            || self
+                .tokens
+                .iter()
+                .any(|t| t.is_comment() || t.is_trailing_comment())
     }
 }
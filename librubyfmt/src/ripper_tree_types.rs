@@ -74,7 +74,7 @@ pub struct Program(pub program_tag, pub Vec<Expression>);
 
 def_tag!(undeserializable, "oiqjweoifjqwoeifjwqoiefjqwoiej");
 #[derive(Deserialize, Debug, Clone)]
-pub struct ToProc(pub undeserializable, pub Box<Expression>);
+pub struct ToProc(pub undeserializable, pub Option<Box<Expression>>);
 
 #[derive(RipperDeserialize, Debug, Clone)]
 pub enum Expression {
@@ -154,6 +154,8 @@ pub enum Expression {
     Unless(Unless),
     ZSuper(ZSuper),
     Yield0(Yield0),
+    ArgsForward(ArgsForward),
+    RightwardAssign(RightwardAssign),
 }
 
 def_tag!(mlhs_tag, "mlhs");
@@ -592,6 +594,13 @@ impl ArgsAddStarOrExpressionList {
     pub fn empty() -> Self {
         ArgsAddStarOrExpressionList::ExpressionList(vec![])
     }
+
+    pub fn last_expression(&self) -> Option<&Expression> {
+        match self {
+            ArgsAddStarOrExpressionList::ExpressionList(el) => el.last(),
+            ArgsAddStarOrExpressionList::ArgsAddStar(aas) => aas.3.last().or(aas.2.as_deref()),
+        }
+    }
 }
 
 def_tag!(args_add_star_tag, "args_add_star");
@@ -599,7 +608,8 @@ def_tag!(args_add_star_tag, "args_add_star");
 pub struct ArgsAddStar(
     pub args_add_star_tag,
     pub Box<ArgsAddStarOrExpressionList>,
-    pub Box<Expression>,
+    // none for an anonymous splat, `foo(*)`
+    pub Option<Box<Expression>>,
     pub Vec<Expression>,
 );
 
@@ -676,9 +686,27 @@ pub struct Def(
     pub def_tag,
     pub IdentOrOpOrKeywordOrConst,
     pub ParenOrParams,
-    pub Box<BodyStmt>,
+    pub DefBody,
 );
 
+#[derive(RipperDeserialize, Debug, Clone)]
+pub enum DefBody {
+    BodyStmt(Box<BodyStmt>),
+    Endless(EndlessBodyStmt),
+}
+
+// `def foo(bar) = expression`, where ripper puts the expression in the
+// bodystmt in place of the list of statements. The rescue, else and ensure
+// slots after it are always nil, and the deserializer skips them
+#[derive(Deserialize, Debug, Clone)]
+pub struct EndlessBodyStmt(bodystmt_tag, pub Box<Expression>);
+
+impl EndlessBodyStmt {
+    pub fn into_bodystmt(self) -> Box<BodyStmt> {
+        Box::new(BodyStmt(bodystmt_tag, vec![*self.1], None, None, None))
+    }
+}
+
 #[derive(RipperDeserialize, Debug, Clone)]
 pub enum IdentOrOpOrKeywordOrConst {
     Ident(Ident),
@@ -884,12 +912,19 @@ pub enum RestParamOr0OrExcessedComma {
     Zero(i64),
     RestParam(RestParam),
     ExcessedComma(ExcessedComma),
+    ArgsForward(ArgsForward),
 }
 
 def_tag!(excessed_comma_tag, "excessed_comma");
 #[derive(Deserialize, Debug, Clone)]
 pub struct ExcessedComma(excessed_comma_tag);
 
+// `...`, which ripper puts in the rest param's place in `def foo(...)`, and
+// in place of the arguments in `foo(...)`
+def_tag!(args_forward_tag, "args_forward");
+#[derive(Deserialize, Debug, Clone)]
+pub struct ArgsForward(args_forward_tag);
+
 impl Params {
     pub fn non_null_positions(&self) -> Vec<bool> {
         vec![
@@ -920,7 +955,7 @@ pub struct KwRestParam(pub kw_rest_param_tag, pub Option<Ident>);
 
 def_tag!(blockarg_tag, "blockarg");
 #[derive(Deserialize, Debug, Clone)]
-pub struct BlockArg(pub blockarg_tag, pub Ident);
+pub struct BlockArg(pub blockarg_tag, pub Option<Ident>);
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct LineCol(pub LineNumber, pub u64);
@@ -984,6 +1019,9 @@ pub fn normalize_args(arg_node: ArgNode) -> ArgsAddStarOrExpressionList {
         ArgNode::Ident(c) => {
             ArgsAddStarOrExpressionList::ExpressionList(vec![Expression::Ident(c)])
         }
+        ArgNode::ArgsForward(af) => {
+            ArgsAddStarOrExpressionList::ExpressionList(vec![Expression::ArgsForward(af)])
+        }
         ArgNode::Null(_) => panic!("should never be called with null"),
     }
 }
@@ -996,6 +1034,7 @@ pub enum ArgNode {
     Exprs(Vec<Expression>),
     Const(Const),
     Ident(Ident),
+    ArgsForward(ArgsForward),
     Null(Option<String>),
 }
 
@@ -1007,7 +1046,8 @@ pub struct ArgParen(pub arg_paren_tag, pub Box<ArgNode>);
 #[derive(RipperDeserialize, Debug, Clone)]
 pub enum ToProcExpr {
     NotPresent(bool),
-    Present(Box<Expression>),
+    // ripper gives us nil for an anonymous block, `foo(&)`
+    Present(Option<Box<Expression>>),
 }
 
 // ArgsAddBlock
@@ -1073,11 +1113,12 @@ pub enum AssocNewOrAssocSplat {
 
 def_tag!(assoc_new_tag, "assoc_new");
 #[derive(Deserialize, Debug, Clone)]
-pub struct AssocNew(pub assoc_new_tag, pub AssocKey, pub Expression);
+// the value is none when it's omitted, `{x:, y:}`
+pub struct AssocNew(pub assoc_new_tag, pub AssocKey, pub Option<Expression>);
 
 def_tag!(assoc_splat_tag, "assoc_splat");
 #[derive(Deserialize, Debug, Clone)]
-pub struct AssocSplat(pub assoc_splat_tag, pub Expression);
+pub struct AssocSplat(pub assoc_splat_tag, pub Option<Expression>);
 
 #[derive(RipperDeserialize, Debug, Clone)]
 pub enum AssocKey {
@@ -1513,7 +1554,7 @@ pub struct Defs(
     pub DotOrColon,
     pub IdentOrOpOrKeywordOrConst,
     pub ParenOrParams,
-    pub Box<BodyStmt>,
+    pub DefBody,
 );
 
 #[derive(RipperDeserialize, Debug, Clone)]
@@ -1690,7 +1731,18 @@ pub struct UntilMod(until_mod_tag, pub Box<Expression>, pub Box<Expression>);
 
 def_tag!(case_tag, "case");
 #[derive(Deserialize, Debug, Clone)]
-pub struct Case(case_tag, pub Option<Box<Expression>>, pub When, pub LineCol);
+pub struct Case(
+    case_tag,
+    pub Option<Box<Expression>>,
+    pub WhenOrIn,
+    pub LineCol,
+);
+
+#[derive(RipperDeserialize, Debug, Clone)]
+pub enum WhenOrIn {
+    When(When),
+    In(Box<In>),
+}
 
 def_tag!(when_tag, "when");
 #[derive(Deserialize, Debug, Clone)]
@@ -1708,6 +1760,112 @@ pub enum WhenOrElse {
     Else(CaseElse),
 }
 
+def_tag!(in_tag, "in");
+#[derive(Deserialize, Debug, Clone)]
+pub struct In(
+    in_tag,
+    pub GuardedPattern,
+    // there's no body for a one line pattern match, `expr in pattern`
+    pub Option<Vec<Expression>>,
+    pub Option<Box<InOrElse>>,
+    pub LineCol,
+);
+
+#[derive(RipperDeserialize, Debug, Clone)]
+pub enum InOrElse {
+    In(In),
+    Else(CaseElse),
+}
+
+// ripper gives us `in pattern if guard` as a modifier conditional wrapping
+// the pattern
+#[derive(RipperDeserialize, Debug, Clone)]
+pub enum GuardedPattern {
+    If(PatternIfGuard),
+    Unless(PatternUnlessGuard),
+    Unguarded(Pattern),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatternIfGuard(if_mod_tag, pub Box<Expression>, pub Pattern);
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatternUnlessGuard(unless_mod_tag, pub Box<Expression>, pub Pattern);
+
+#[derive(RipperDeserialize, Debug, Clone)]
+pub enum Pattern {
+    AryPtn(Box<AryPtn>),
+    FndPtn(Box<FndPtn>),
+    HshPtn(Box<HshPtn>),
+    // `pattern | pattern` and `pattern => binding`
+    Binary(Box<PatternBinary>),
+    // `^(expression)`
+    PinnedExpression(PinnedExpression),
+    // a bare identifier binds whatever it's matched against
+    VarField(VarField),
+    // anything else is matched with `===`, a local variable here is a
+    // pinned variable, `^x`
+    Value(Box<Expression>),
+}
+
+def_tag!(aryptn_tag, "aryptn");
+#[derive(Deserialize, Debug, Clone)]
+pub struct AryPtn(
+    aryptn_tag,
+    pub Option<Box<Expression>>,
+    pub Option<Vec<Pattern>>,
+    pub Option<PatternRest>,
+    pub Option<Vec<Pattern>>,
+    pub Option<String>,
+);
+
+def_tag!(fndptn_tag, "fndptn");
+#[derive(Deserialize, Debug, Clone)]
+pub struct FndPtn(
+    fndptn_tag,
+    pub Option<Box<Expression>>,
+    pub PatternRest,
+    pub Vec<Pattern>,
+    pub PatternRest,
+    pub Option<String>,
+);
+
+def_tag!(hshptn_tag, "hshptn");
+#[derive(Deserialize, Debug, Clone)]
+pub struct HshPtn(
+    hshptn_tag,
+    pub Option<Box<Expression>>,
+    pub Option<Vec<(Label, Option<Pattern>)>>,
+    pub Option<KwRestPattern>,
+    pub Option<String>,
+);
+
+// `*name`, or just `*` when the rest isn't bound
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatternRest(var_field_tag, pub Option<Ident>);
+
+#[derive(RipperDeserialize, Debug, Clone)]
+pub enum KwRestPattern {
+    Rest(PatternRest),
+    // `**nil`
+    NoRest((var_field_tag, String)),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PatternBinary(binary_tag, pub Pattern, pub String, pub Pattern);
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PinnedExpression(begin_tag, pub Box<Expression>);
+
+def_tag!(rightward_assign_tag, "rightward_assign");
+#[derive(Deserialize, Debug, Clone)]
+pub struct RightwardAssign(
+    rightward_assign_tag,
+    pub Box<Expression>,
+    pub Pattern,
+    pub LineCol,
+);
+
 def_tag!(case_else_tag, "else");
 #[derive(Deserialize, Debug, Clone)]
 pub struct CaseElse(case_else_tag, pub Vec<Expression>);
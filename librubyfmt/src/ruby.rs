@@ -48,6 +48,10 @@ pub enum ruby_value_type {
 
 #[allow(non_upper_case_globals)]
 pub const Qnil: VALUE = VALUE(8);
+#[allow(non_upper_case_globals)]
+pub const Qtrue: VALUE = VALUE(0x14);
+#[allow(non_upper_case_globals)]
+pub const Qfalse: VALUE = VALUE(0);
 
 extern "C" {
     // stuff that we need to compile out rubyfmt
@@ -55,7 +59,9 @@ extern "C" {
     pub fn ruby_cleanup(_: libc::c_int);
     pub fn rb_eval_string_protect(_: *const libc::c_char, _: *mut libc::c_int) -> VALUE;
     pub fn rb_funcall(_: VALUE, _: ID, _: libc::c_int, ...) -> VALUE;
+    pub fn rb_funcallv(_: VALUE, _: ID, _: libc::c_int, _: *const VALUE) -> VALUE;
     pub fn rb_utf8_str_new(_: *const libc::c_char, _: libc::c_long) -> VALUE;
+    pub fn rb_str_new(_: *const libc::c_char, _: libc::c_long) -> VALUE;
     pub fn rb_str_new_cstr(_: *const libc::c_char) -> VALUE;
     pub fn rb_string_value_cstr(_: *const VALUE) -> *const libc::c_char;
     pub fn rb_intern(_: *const libc::c_char) -> ID;
@@ -77,12 +83,34 @@ extern "C" {
     pub static rb_eRuntimeError: VALUE;
     pub static rb_mKernel: VALUE;
     pub static rb_cObject: VALUE;
+    pub static rb_eStandardError: VALUE;
+    pub static rb_eArgError: VALUE;
+    pub static rb_eTypeError: VALUE;
 
     // C functions
     pub fn rb_sym2id(sym: VALUE) -> ID;
     pub fn rb_id2name(id: ID) -> *const libc::c_char;
     pub fn rb_ary_entry(arr: VALUE, idx: libc::c_long) -> VALUE;
+    pub fn rb_ary_new_from_values(n: libc::c_long, elts: *const VALUE) -> VALUE;
     pub fn rb_raise(cls: VALUE, msg: *const libc::c_char);
+    pub fn rb_exc_new(cls: VALUE, msg: *const libc::c_char, len: libc::c_long) -> VALUE;
+    pub fn rb_exc_raise(exception: VALUE) -> !;
+    pub fn rb_id2sym(id: ID) -> VALUE;
+    pub fn rb_hash_aref(hash: VALUE, key: VALUE) -> VALUE;
+    pub fn rb_gc_disable() -> VALUE;
+    pub fn rb_gc_enable() -> VALUE;
+    pub fn rb_define_module(name: *const libc::c_char) -> VALUE;
+    pub fn rb_define_class_under(
+        outer: VALUE,
+        name: *const libc::c_char,
+        superclass: VALUE,
+    ) -> VALUE;
+    pub fn rb_define_module_function(
+        module: VALUE,
+        name: *const libc::c_char,
+        func: *const libc::c_void,
+        argc: libc::c_int,
+    );
     pub fn rb_block_call(
         obj: VALUE,
         method_id: ID,
@@ -0,0 +1,289 @@
+use crate::ffi::{self, FfiError};
+use crate::file_io;
+use crate::format_options::{
+    BlankLinePolicy, CommentAlignment, FormatOptions, LineEndings, QuoteStyle, TrailingCommas,
+};
+use crate::ruby::*;
+use crate::ruby_ops::rubyfmt_module;
+use crate::{InitStatus, RichFormatError};
+use std::ffi::CStr;
+use std::slice;
+
+// Something for a Rubyfmt method to raise. We build these in rust and only
+// raise once everything rust owns has been dropped, because raising longjmps
+// straight past any destructors.
+struct RubyError {
+    class: VALUE,
+    message: String,
+}
+
+impl RubyError {
+    fn new(class: VALUE, message: String) -> Self {
+        RubyError { class, message }
+    }
+}
+
+impl From<FfiError> for RubyError {
+    fn from(e: FfiError) -> Self {
+        let class = unsafe {
+            match e {
+                FfiError::Format(RichFormatError::SyntaxError(_)) => {
+                    rb_const_get_at(rubyfmt_module(), intern!("SyntaxError"))
+                }
+                _ => rb_const_get_at(rubyfmt_module(), intern!("Error")),
+            }
+        };
+        RubyError::new(class, e.to_string())
+    }
+}
+
+/// Called by ruby when the extension is required. Defines `Rubyfmt.format`
+/// and `Rubyfmt.formatted?`, which both take a source string and the same
+/// options as `FormatOptions` as keyword arguments.
+///
+/// # Safety
+/// must only be called by ruby, while it's loading the extension
+#[no_mangle]
+pub unsafe extern "C" fn Init_rubyfmt() {
+    let module = rb_define_module("Rubyfmt\0".as_ptr() as _);
+    let error = rb_define_class_under(module, "Error\0".as_ptr() as _, rb_eStandardError);
+    rb_define_class_under(module, "SyntaxError\0".as_ptr() as _, error);
+    rb_define_module_function(module, "format\0".as_ptr() as _, format as _, -1);
+    rb_define_module_function(module, "formatted?\0".as_ptr() as _, is_formatted as _, -1);
+
+    if ffi::rubyfmt_init() != InitStatus::OK as libc::c_int {
+        raise(RubyError::new(
+            error,
+            "failed to load rubyfmt in to this ruby".to_string(),
+        ));
+    }
+}
+
+unsafe extern "C" fn format(argc: libc::c_int, argv: *const VALUE, _module: VALUE) -> VALUE {
+    let formatted = without_gc(|| {
+        let (source, output) = format_args(argc, argv)?;
+        let output_string = rb_str_new(output.as_ptr() as _, output.len() as _);
+
+        // the output is in the same encoding as the source
+        let encoding = protected_funcall(source, intern!("encoding"), &[])?;
+        protected_funcall(output_string, intern!("force_encoding"), &[encoding])
+    });
+    match formatted {
+        Ok(output_string) => output_string,
+        Err(e) => raise(e),
+    }
+}
+
+unsafe extern "C" fn is_formatted(argc: libc::c_int, argv: *const VALUE, _module: VALUE) -> VALUE {
+    let is_formatted = without_gc(|| {
+        let (source, output) = format_args(argc, argv)?;
+        let input = slice::from_raw_parts(
+            rubyfmt_rstring_ptr(source) as *const u8,
+            rubyfmt_rstring_len(source) as _,
+        );
+        Ok(input == &output[..])
+    });
+    match is_formatted {
+        Ok(true) => Qtrue,
+        Ok(false) => Qfalse,
+        Err(e) => raise(e),
+    }
+}
+
+// The host's GC can't see the ruby objects we hold on to from rust, from
+// reading the options hash right through to building the output string, so
+// hold it off for the whole call. This has to be undone before raising, as
+// raising longjmps past anything after it.
+unsafe fn without_gc<T>(f: impl FnOnce() -> Result<T, RubyError>) -> Result<T, RubyError> {
+    let gc_was_disabled = rb_gc_disable() == Qtrue;
+    let res = f();
+    if !gc_was_disabled {
+        rb_gc_enable();
+    }
+    res
+}
+
+unsafe fn raise(error: RubyError) -> ! {
+    let exception = rb_exc_new(
+        error.class,
+        error.message.as_ptr() as _,
+        error.message.len() as _,
+    );
+    drop(error);
+    rb_exc_raise(exception)
+}
+
+unsafe extern "C" fn real_funcall(call: VALUE) -> VALUE {
+    match ruby_array_to_slice(call) {
+        [receiver, method, args @ ..] => rb_funcallv(
+            *receiver,
+            rb_sym2id(*method),
+            args.len() as _,
+            args.as_ptr(),
+        ),
+        other => panic!(
+            "expected a call to contain a receiver and a method, actually got: {} elements",
+            other.len(),
+        ),
+    }
+}
+
+// Calls a method on one of the host's objects. Those can be anything the
+// caller passed in, with methods they've redefined, so anything this raises
+// is caught and raised again as a Rubyfmt::Error once rust is done, rather
+// than longjmping straight past our destructors.
+unsafe fn protected_funcall(
+    receiver: VALUE,
+    method: ID,
+    args: &[VALUE],
+) -> Result<VALUE, RubyError> {
+    let call: Vec<VALUE> = [receiver, rb_id2sym(method)]
+        .iter()
+        .chain(args)
+        .copied()
+        .collect();
+    let call = rb_ary_new_from_values(call.len() as _, call.as_ptr());
+    let mut state = 0;
+    let result = rb_protect(real_funcall as _, call, &mut state);
+    if state != 0 {
+        return Err(RubyError::new(
+            rb_const_get_at(rubyfmt_module(), intern!("Error")),
+            current_exception_as_rust_string(),
+        ));
+    }
+    Ok(result)
+}
+
+// Formats the arguments to a Rubyfmt method, `(source, **options)`, returning
+// the source and the formatted output
+unsafe fn format_args(
+    argc: libc::c_int,
+    argv: *const VALUE,
+) -> Result<(VALUE, Vec<u8>), RubyError> {
+    let args = if argc > 0 {
+        slice::from_raw_parts(argv, argc as _)
+    } else {
+        &[]
+    };
+    let (source, opts) = match args {
+        [source] => (*source, FormatOptions::default()),
+        [source, opts] if matches!(rubyfmt_rb_type(*opts), ruby_value_type::RUBY_T_HASH) => {
+            (*source, options_from_hash(*opts)?)
+        }
+        _ => {
+            return Err(RubyError::new(
+                rb_eArgError,
+                format!(
+                    "wrong number of arguments (given {}, expected 1)",
+                    args.len()
+                ),
+            ))
+        }
+    };
+    if !matches!(rubyfmt_rb_type(source), ruby_value_type::RUBY_T_STRING) {
+        return Err(RubyError::new(
+            rb_eTypeError,
+            "source must be a String".to_string(),
+        ));
+    }
+
+    let input = slice::from_raw_parts(
+        rubyfmt_rstring_ptr(source) as *const u8,
+        rubyfmt_rstring_len(source) as _,
+    );
+    let output = ffi::with_vm(|| file_io::format_bytes(input, &opts))?;
+    Ok((source, output))
+}
+
+unsafe fn options_from_hash(hash: VALUE) -> Result<FormatOptions, RubyError> {
+    let mut opts = FormatOptions::default();
+    let keys = protected_funcall(hash, intern!("keys"), &[])?;
+    for key in ruby_array_to_slice(keys) {
+        let name = symbol_name(*key).ok_or_else(|| {
+            RubyError::new(rb_eArgError, "option names must be symbols".to_string())
+        })?;
+        let value = rb_hash_aref(hash, *key);
+        match name.as_str() {
+            "line_width" => opts.line_width = integer_option(&name, value)? as _,
+            "indent_width" => opts.indent_width = integer_option(&name, value)? as _,
+            "quote_style" => {
+                opts.quote_style = match symbol_option(&name, value)?.as_str() {
+                    "double" => QuoteStyle::Double,
+                    "prefer_single" => QuoteStyle::PreferSingle,
+                    other => return Err(invalid_option(&name, other)),
+                }
+            }
+            "trailing_commas" => {
+                opts.trailing_commas = match symbol_option(&name, value)?.as_str() {
+                    "never" => TrailingCommas::Never,
+                    "multiline" => TrailingCommas::Multiline,
+                    other => return Err(invalid_option(&name, other)),
+                }
+            }
+            "blank_lines" => {
+                opts.blank_lines = match symbol_option(&name, value)?.as_str() {
+                    "preserve" => BlankLinePolicy::Preserve,
+                    "remove" => BlankLinePolicy::Remove,
+                    other => return Err(invalid_option(&name, other)),
+                }
+            }
+            "line_endings" => {
+                opts.line_endings = match symbol_option(&name, value)?.as_str() {
+                    "preserve" => LineEndings::Preserve,
+                    "lf" => LineEndings::Lf,
+                    "crlf" => LineEndings::Crlf,
+                    other => return Err(invalid_option(&name, other)),
+                }
+            }
+            "comment_alignment" => {
+                opts.comment_alignment = match symbol_option(&name, value)?.as_str() {
+                    "never" => CommentAlignment::Never,
+                    "consecutive" => CommentAlignment::Consecutive,
+                    other => return Err(invalid_option(&name, other)),
+                }
+            }
+            _ => {
+                return Err(RubyError::new(
+                    rb_eArgError,
+                    format!("unknown option: {}", name),
+                ))
+            }
+        }
+    }
+    Ok(opts)
+}
+
+unsafe fn symbol_name(v: VALUE) -> Option<String> {
+    if !matches!(rubyfmt_rb_type(v), ruby_value_type::RUBY_T_SYMBOL) {
+        return None;
+    }
+    let name = CStr::from_ptr(rb_id2name(rb_sym2id(v)));
+    Some(name.to_string_lossy().into_owned())
+}
+
+unsafe fn symbol_option(name: &str, v: VALUE) -> Result<String, RubyError> {
+    symbol_name(v)
+        .ok_or_else(|| RubyError::new(rb_eTypeError, format!("{} must be a Symbol", name)))
+}
+
+unsafe fn integer_option(name: &str, v: VALUE) -> Result<u32, RubyError> {
+    let value = if matches!(rubyfmt_rb_type(v), ruby_value_type::RUBY_T_FIXNUM) {
+        rubyfmt_rb_num2ll(v)
+    } else {
+        return Err(RubyError::new(
+            rb_eTypeError,
+            format!("{} must be an Integer", name),
+        ));
+    };
+    if value <= 0 || value > u32::MAX as _ {
+        return Err(invalid_option(name, &value.to_string()));
+    }
+    Ok(value as _)
+}
+
+fn invalid_option(name: &str, value: &str) -> RubyError {
+    RubyError::new(
+        unsafe { rb_eArgError },
+        format!("invalid value for {}: {}", name, value),
+    )
+}
@@ -1,6 +1,10 @@
 use crate::file_comments::FileComments;
 use crate::ruby::*;
+use crate::types::LineNumber;
+use std::collections::BTreeMap;
+use std::fmt;
 
+#[cfg(not(feature = "ruby_extension"))]
 pub fn setup_ruby() -> Result<(), ()> {
     unsafe {
         let res = ruby_setup();
@@ -19,53 +23,155 @@ pub unsafe fn load_rubyfmt() -> Result<(), ()> {
     Ok(())
 }
 
+// rubyfmt_lib.rb defines everything under this module
+pub unsafe fn rubyfmt_module() -> VALUE {
+    rb_const_get_at(rb_cObject, intern!("Rubyfmt"))
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct Parser(VALUE);
 
 #[derive(Debug, Clone)]
 pub enum ParseError {
-    SyntaxError,
+    SyntaxError(Vec<SyntaxErrorDetail>),
     OtherRubyError(String),
 }
 
+/// A single error reported by ripper. `column` is zero indexed, as ripper
+/// reports it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxErrorDetail {
+    pub line_number: LineNumber,
+    pub column: u64,
+    pub message: String,
+}
+
+impl fmt::Display for SyntaxErrorDetail {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line_number, self.column, self.message)
+    }
+}
+
 impl Parser {
     unsafe extern "C" fn real_run_parser(parser_instance: VALUE) -> VALUE {
         rb_funcall(parser_instance, intern!("parse"), 0)
     }
 
+    unsafe extern "C" fn real_statement_spans(parser_instance: VALUE) -> VALUE {
+        rb_funcall(parser_instance, intern!("statement_spans"), 0)
+    }
+
     pub fn new(buf: &str) -> Self {
         unsafe {
             let buffer_string = rb_utf8_str_new(buf.as_ptr() as _, buf.len() as i64);
-            let parser_class = rb_const_get_at(rb_cObject, intern!("Parser"));
+            let parser_class = rb_const_get_at(rubyfmt_module(), intern!("Parser"));
             let parser_instance = rb_funcall(parser_class, intern!("new"), 1, buffer_string);
             Parser(parser_instance)
         }
     }
 
-    pub fn parse(self) -> Result<(RipperTree, FileComments), ParseError> {
+    /// Parses the buffer, returning the tree, its comments, and the line of
+    /// its `__END__`, if it has one
+    pub fn parse(self) -> Result<(RipperTree, FileComments, Option<LineNumber>), ParseError> {
         let mut state = 0;
         let maybe_tree_and_comments =
             unsafe { rb_protect(Parser::real_run_parser as _, self.0 as _, &mut state) };
         if state == 0 {
             if maybe_tree_and_comments != Qnil {
                 let tree_and_comments = unsafe { ruby_array_to_slice(maybe_tree_and_comments) };
-                if let [tree, comments] = tree_and_comments {
+                if let [tree, comments, end_line] = tree_and_comments {
                     let fc = FileComments::from_ruby_hash(*comments);
-                    Ok((RipperTree::new(*tree), fc))
+                    let end_line = if *end_line == Qnil {
+                        None
+                    } else {
+                        Some(unsafe { rubyfmt_rb_num2ll(*end_line) } as LineNumber)
+                    };
+                    Ok((RipperTree::new(*tree), fc, end_line))
                 } else {
                     panic!(
-                        "expected tree to contain two elements, actually got: {}",
+                        "expected tree to contain three elements, actually got: {}",
                         tree_and_comments.len(),
                     )
                 }
             } else {
-                Err(ParseError::SyntaxError)
+                Err(ParseError::SyntaxError(self.syntax_errors()))
             }
         } else {
             let s = current_exception_as_rust_string();
             Err(ParseError::OtherRubyError(s))
         }
     }
+
+    /// Parses the buffer without building anything from the tree, returning
+    /// every error ripper reported. Unlike `parse`, this also reports errors
+    /// that ripper recovered from, so it rejects exactly what `ruby -c` would.
+    pub fn syntax_check(self) -> Result<Vec<SyntaxErrorDetail>, ParseError> {
+        let mut state = 0;
+        let maybe_tree_and_comments =
+            unsafe { rb_protect(Parser::real_run_parser as _, self.0 as _, &mut state) };
+        if state != 0 {
+            let s = current_exception_as_rust_string();
+            return Err(ParseError::OtherRubyError(s));
+        }
+
+        let mut errors = self.syntax_errors();
+        if errors.is_empty() && maybe_tree_and_comments == Qnil {
+            errors.push(SyntaxErrorDetail {
+                line_number: 0,
+                column: 0,
+                message: "syntax error".to_string(),
+            });
+        }
+        Ok(errors)
+    }
+
+    /// The first and last line of each statement `# rubyfmt:` directives
+    /// could protect, keyed by the first. This is empty unless the buffer
+    /// has a directive in it, and is only filled in once it's been parsed.
+    pub fn statement_spans(&self) -> Result<BTreeMap<LineNumber, LineNumber>, ParseError> {
+        let mut state = 0;
+        let spans =
+            unsafe { rb_protect(Parser::real_statement_spans as _, self.0 as _, &mut state) };
+        if state != 0 {
+            let s = current_exception_as_rust_string();
+            return Err(ParseError::OtherRubyError(s));
+        }
+
+        unsafe {
+            let firsts = ruby_array_to_slice(rb_funcall(spans, intern!("keys"), 0));
+            let lasts = ruby_array_to_slice(rb_funcall(spans, intern!("values"), 0));
+            Ok(firsts
+                .iter()
+                .zip(lasts)
+                .map(|(first, last)| {
+                    (
+                        rubyfmt_rb_num2ll(*first) as LineNumber,
+                        rubyfmt_rb_num2ll(*last) as LineNumber,
+                    )
+                })
+                .collect())
+        }
+    }
+
+    fn syntax_errors(&self) -> Vec<SyntaxErrorDetail> {
+        unsafe {
+            let errors = rb_funcall(self.0, intern!("syntax_errors"), 0);
+            ruby_array_to_slice(errors)
+                .iter()
+                .map(|error| match ruby_array_to_slice(*error) {
+                    [line_number, column, message] => SyntaxErrorDetail {
+                        line_number: rubyfmt_rb_num2ll(*line_number) as _,
+                        column: rubyfmt_rb_num2ll(*column) as _,
+                        message: ruby_string_to_str(*message).to_owned(),
+                    },
+                    other => panic!(
+                        "expected syntax error to contain three elements, actually got: {}",
+                        other.len(),
+                    ),
+                })
+                .collect()
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -0,0 +1,194 @@
+use crate::ruby::*;
+use crate::RichFormatError;
+use std::ops::Range;
+use std::slice;
+use std::str;
+
+/// The encoding a source file is written in. Rubyfmt only ever formats
+/// UTF-8, so files that declare another encoding in a magic comment are
+/// transcoded to UTF-8 before formatting, and back again afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceEncoding {
+    Utf8,
+    Declared {
+        // the encoding name, exactly as written in the magic comment
+        name: String,
+        // the magic comment as written in the file
+        magic_comment: String,
+        // the magic comment we parse instead, declaring utf-8, so that ruby
+        // doesn't try to read the transcoded buffer as the original encoding
+        utf8_magic_comment: String,
+    },
+}
+
+impl SourceEncoding {
+    /// Turns raw source bytes in to a UTF-8 buffer ready for formatting,
+    /// along with the encoding needed to write the output back.
+    pub fn decode(bytes: &[u8]) -> Result<(String, SourceEncoding), RichFormatError> {
+        let name = match find_magic_encoding(bytes) {
+            Some(magic) if !is_utf8(&magic.name) => magic.name,
+            _ => {
+                let buf = str::from_utf8(bytes).map_err(RichFormatError::InvalidUtf8)?;
+                return Ok((buf.to_string(), SourceEncoding::Utf8));
+            }
+        };
+
+        let transcoded = transcode(bytes, transcoding_name(&name), "UTF-8")?;
+        let buf = String::from_utf8(transcoded)
+            .expect("ruby always transcodes to valid UTF-8, or raises");
+        let magic =
+            find_magic_encoding(buf.as_bytes()).expect("transcoding kept the magic comment");
+        let magic_comment = buf[magic.comment_start..magic.comment_end].to_string();
+        let utf8_magic_comment = format!(
+            "{}utf-8{}",
+            &buf[magic.comment_start..magic.name_range.start],
+            &buf[magic.name_range.end..magic.comment_end],
+        );
+        let buf = buf.replacen(&magic_comment, &utf8_magic_comment, 1);
+
+        Ok((
+            buf,
+            SourceEncoding::Declared {
+                name,
+                magic_comment,
+                utf8_magic_comment,
+            },
+        ))
+    }
+
+    /// Turns formatted output back in to bytes in this encoding
+    pub fn encode(&self, output: String) -> Result<Vec<u8>, RichFormatError> {
+        match self {
+            SourceEncoding::Utf8 => Ok(output.into_bytes()),
+            SourceEncoding::Declared {
+                name,
+                magic_comment,
+                utf8_magic_comment,
+            } => {
+                let output = output.replacen(utf8_magic_comment, magic_comment, 1);
+                transcode(output.as_bytes(), "UTF-8", transcoding_name(name))
+            }
+        }
+    }
+}
+
+struct MagicEncoding {
+    name: String,
+    comment_start: usize,
+    comment_end: usize,
+    name_range: Range<usize>,
+}
+
+// ruby only looks for a magic comment on the first line, or the second if
+// the first is a shebang. This matches `coding: name` and `coding=name`,
+// which covers the plain, emacs and vim styles.
+fn find_magic_encoding(bytes: &[u8]) -> Option<MagicEncoding> {
+    let mut line_start = 0;
+    for line in bytes.split(|b| *b == b'\n').take(2) {
+        let comment_start = line_start + line.iter().position(|b| !b" \t".contains(b))?;
+        let comment_end = line_start + line.len()
+            - line
+                .iter()
+                .rev()
+                .take_while(|b| b" \t\r".contains(b))
+                .count();
+        if bytes[comment_start] != b'#' {
+            return None;
+        }
+
+        if let Some(name_range) = find_coding_name(&bytes[comment_start..comment_end]) {
+            let name_range = comment_start + name_range.start..comment_start + name_range.end;
+            return Some(MagicEncoding {
+                name: String::from_utf8_lossy(&bytes[name_range.clone()]).into_owned(),
+                comment_start,
+                comment_end,
+                name_range,
+            });
+        }
+
+        if !line.starts_with(b"#!") {
+            return None;
+        }
+        line_start += line.len() + 1;
+    }
+    None
+}
+
+fn find_coding_name(comment: &[u8]) -> Option<Range<usize>> {
+    let lowercase = comment.to_ascii_lowercase();
+    let mut search_from = 0;
+    while let Some(offset) = lowercase[search_from..]
+        .windows(b"coding".len())
+        .position(|w| w == b"coding")
+    {
+        let mut idx = search_from + offset + b"coding".len();
+        search_from = idx;
+        if idx >= comment.len() || (comment[idx] != b':' && comment[idx] != b'=') {
+            continue;
+        }
+        idx += 1;
+        while idx < comment.len() && b" \t".contains(&comment[idx]) {
+            idx += 1;
+        }
+        let name_start = idx;
+        while idx < comment.len()
+            && (comment[idx].is_ascii_alphanumeric() || b"_.-".contains(&comment[idx]))
+        {
+            idx += 1;
+        }
+        if idx > name_start {
+            return Some(name_start..idx);
+        }
+    }
+    None
+}
+
+fn is_utf8(name: &str) -> bool {
+    name.eq_ignore_ascii_case("utf-8")
+}
+
+// binary files can contain any byte, which ruby refuses to transcode to
+// UTF-8. Every byte is a valid character in ISO-8859-1 though, so going
+// through that round trips the original bytes exactly.
+fn transcoding_name(name: &str) -> &str {
+    if name.eq_ignore_ascii_case("binary") || name.eq_ignore_ascii_case("ascii-8bit") {
+        "ISO-8859-1"
+    } else {
+        name
+    }
+}
+
+unsafe extern "C" fn real_transcode(args: VALUE) -> VALUE {
+    match ruby_array_to_slice(args) {
+        [source, to, from] => rb_funcall(*source, intern!("encode"), 2, *to, *from),
+        other => panic!(
+            "expected transcode args to contain three elements, actually got: {}",
+            other.len(),
+        ),
+    }
+}
+
+// transcodes using the embedded ruby's `String#encode`, which raises if
+// either encoding is unknown or the bytes can't be represented in `to`
+fn transcode(bytes: &[u8], from: &str, to: &str) -> Result<Vec<u8>, RichFormatError> {
+    unsafe {
+        let args = [
+            rb_str_new(bytes.as_ptr() as _, bytes.len() as _),
+            rb_utf8_str_new(to.as_ptr() as _, to.len() as _),
+            rb_utf8_str_new(from.as_ptr() as _, from.len() as _),
+        ];
+        let args = rb_ary_new_from_values(args.len() as _, args.as_ptr());
+        let mut state = 0;
+        let transcoded = rb_protect(real_transcode as _, args, &mut state);
+        if state != 0 {
+            return Err(RichFormatError::InvalidEncoding(
+                current_exception_as_rust_string(),
+            ));
+        }
+        Ok(slice::from_raw_parts(
+            rubyfmt_rstring_ptr(transcoded) as *const u8,
+            rubyfmt_rstring_len(transcoded) as _,
+        )
+        .to_vec())
+    }
+}
@@ -0,0 +1,74 @@
+use crate::format_options::{FormatOptions, LineEndings};
+
+pub const BYTE_ORDER_MARK: char = '\u{feff}';
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::Crlf => "\r\n",
+        }
+    }
+}
+
+/// What we learn about a buffer's layout before parsing it, so that
+/// `RenderQueueWriter` can lay the output out the same way.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SourceLayout {
+    pub line_ending: LineEnding,
+    pub byte_order_mark: bool,
+}
+
+impl Default for SourceLayout {
+    fn default() -> Self {
+        SourceLayout {
+            line_ending: LineEnding::Lf,
+            byte_order_mark: false,
+        }
+    }
+}
+
+impl SourceLayout {
+    /// Finds the dominant line ending and any BOM in `buf`, and returns it
+    /// without the BOM, ready for ripper. The line endings are left alone:
+    /// ripper ends lines at `\r\n` by itself, and leaving them means string
+    /// literals and heredocs keep the bytes they were written with.
+    pub fn normalize(buf: &str) -> (&str, SourceLayout) {
+        let crlf_count = buf.matches("\r\n").count();
+        let lf_count = buf.matches('\n').count() - crlf_count;
+        let layout = SourceLayout {
+            line_ending: if crlf_count > lf_count {
+                LineEnding::Crlf
+            } else {
+                LineEnding::Lf
+            },
+            byte_order_mark: buf.starts_with(BYTE_ORDER_MARK),
+        };
+
+        let buf = if layout.byte_order_mark {
+            &buf[BYTE_ORDER_MARK.len_utf8()..]
+        } else {
+            buf
+        };
+        (buf, layout)
+    }
+
+    /// Applies `FormatOptions::line_endings`, if it forces a line ending
+    pub fn with_options(self, opts: &FormatOptions) -> SourceLayout {
+        let line_ending = match opts.line_endings {
+            LineEndings::Preserve => self.line_ending,
+            LineEndings::Lf => LineEnding::Lf,
+            LineEndings::Crlf => LineEnding::Crlf,
+        };
+        SourceLayout {
+            line_ending,
+            ..self
+        }
+    }
+}
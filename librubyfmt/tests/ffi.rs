@@ -0,0 +1,51 @@
+//! The C ABI, called the way a C program would
+
+use rubyfmt::{
+    rubyfmt_default_options, rubyfmt_format_buffer_with_options, rubyfmt_is_formatted,
+    rubyfmt_last_error_message, FormatError, RubyfmtOptions,
+};
+use std::ffi::CStr;
+
+fn last_error_message() -> Option<String> {
+    let message = rubyfmt_last_error_message();
+    if message.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(message) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+#[test]
+fn rejects_zero_widths() {
+    let buf = b"a(1, 2, 3)\n";
+    for options in &[
+        RubyfmtOptions {
+            line_width: 0,
+            ..rubyfmt_default_options()
+        },
+        RubyfmtOptions {
+            indent_width: 0,
+            ..rubyfmt_default_options()
+        },
+    ] {
+        let mut err = FormatError::OK as i64;
+        let output = unsafe {
+            rubyfmt_format_buffer_with_options(buf.as_ptr(), buf.len(), options, &mut err)
+        };
+        assert!(output.is_null());
+        assert_eq!(err, FormatError::InvalidOptions as i64);
+        assert!(last_error_message()
+            .expect("an error message")
+            .starts_with("invalid options:"));
+
+        let mut err = FormatError::OK as i64;
+        let is_formatted =
+            unsafe { rubyfmt_is_formatted(buf.as_ptr(), buf.len(), options, &mut err) };
+        assert!(!is_formatted);
+        assert_eq!(err, FormatError::InvalidOptions as i64);
+    }
+}
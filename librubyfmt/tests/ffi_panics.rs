@@ -0,0 +1,56 @@
+//! A panic inside rubyfmt, made with the test_hooks feature's
+//! rubyfmt_test_panic_next_call, coming back through the C ABI. This uses
+//! the VM, so it's one test, in a test binary of its own.
+
+use rubyfmt::{
+    rubyfmt_format_buffer, rubyfmt_init, rubyfmt_last_error_message, rubyfmt_string_free,
+    rubyfmt_string_len, rubyfmt_string_ptr, rubyfmt_test_panic_next_call, FormatError, InitStatus,
+};
+use std::ffi::CStr;
+use std::slice;
+
+fn last_error_message() -> Option<String> {
+    let message = rubyfmt_last_error_message();
+    if message.is_null() {
+        None
+    } else {
+        Some(
+            unsafe { CStr::from_ptr(message) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+fn format(buf: &[u8]) -> (Option<Vec<u8>>, i64) {
+    let mut err = -1;
+    let output = unsafe { rubyfmt_format_buffer(buf.as_ptr(), buf.len(), &mut err) };
+    if output.is_null() {
+        return (None, err);
+    }
+    let bytes = unsafe {
+        let output = &*output;
+        slice::from_raw_parts(rubyfmt_string_ptr(output), rubyfmt_string_len(output)).to_vec()
+    };
+    unsafe { rubyfmt_string_free(output) };
+    (Some(bytes), err)
+}
+
+#[test]
+fn a_panic_while_formatting_is_reported_and_rubyfmt_carries_on() {
+    assert_eq!(rubyfmt_init(), InitStatus::OK as _);
+
+    rubyfmt_test_panic_next_call();
+    let (output, err) = format(b"a 1\n");
+    assert_eq!(output, None);
+    assert_eq!(err, FormatError::InternalPanic as i64);
+    assert_eq!(
+        last_error_message().as_deref(),
+        Some("rubyfmt panicked: rubyfmt_test_panic_next_call was called"),
+    );
+
+    let (output, err) = format(b"a 1\n");
+    assert_eq!(err, FormatError::OK as i64);
+    assert_eq!(output.as_deref(), Some(&b"a(1)\n"[..]));
+    assert_eq!(last_error_message(), None);
+}
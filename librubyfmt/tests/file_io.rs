@@ -0,0 +1,69 @@
+//! These all fail before the input reaches the VM, so they can run on
+//! whichever threads the harness picks
+
+use rubyfmt::{format_file, format_reader, FormatOptions, FormatTarget, RichFormatError};
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+struct BrokenReader;
+
+impl Read for BrokenReader {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionReset,
+            "the pipe went away",
+        ))
+    }
+}
+
+fn scratch_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rubyfmt_file_io_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn a_missing_file_is_an_io_error() {
+    let path = scratch_path("missing.rb");
+    let result = format_file(&path, FormatTarget::InPlace, &FormatOptions::default());
+    match result {
+        Err(RichFormatError::IOError(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+        other => panic!("expected a NotFound IOError, got {:?}", other),
+    }
+    assert!(
+        !path.exists(),
+        "formatting in place doesn't create the file"
+    );
+}
+
+#[test]
+fn non_utf8_bytes_without_a_magic_comment_are_rejected() {
+    let input: &[u8] = b"a = \"caf\xe9\"\n";
+    let mut output = vec![];
+    let result = format_reader(input, &mut output, &FormatOptions::default());
+    match result {
+        Err(RichFormatError::InvalidUtf8(e)) => assert_eq!(e.valid_up_to(), 8),
+        other => panic!("expected InvalidUtf8, got {:?}", other),
+    }
+    assert!(output.is_empty(), "nothing is written on failure");
+
+    let path = scratch_path("latin1.rb");
+    fs::write(&path, input).expect("writing the scratch file");
+    let result = format_file(&path, FormatTarget::InPlace, &FormatOptions::default());
+    let untouched = fs::read(&path).expect("reading the scratch file");
+    fs::remove_file(&path).expect("removing the scratch file");
+    assert!(matches!(result, Err(RichFormatError::InvalidUtf8(_))));
+    assert_eq!(untouched, input);
+}
+
+#[test]
+fn a_failing_reader_is_an_io_error() {
+    let mut output = vec![];
+    let result = format_reader(BrokenReader, &mut output, &FormatOptions::default());
+    match result {
+        Err(RichFormatError::IOError(e)) => {
+            assert_eq!(e.kind(), io::ErrorKind::ConnectionReset)
+        }
+        other => panic!("expected a ConnectionReset IOError, got {:?}", other),
+    }
+    assert!(output.is_empty(), "nothing is written on failure");
+}
@@ -0,0 +1,110 @@
+//! Formats the fixtures in fixtures/small/options with the options their
+//! names start with, checking the output is the matching `_expected.rb` and
+//! that formatting that again doesn't change it
+
+use rubyfmt::{
+    BlankLinePolicy, CommentAlignment, FormatOptions, Formatter, QuoteStyle, TrailingCommas,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn options_for(fixture: &str) -> FormatOptions {
+    let defaults = FormatOptions::default();
+    if fixture.starts_with("trailing_commas") {
+        FormatOptions {
+            trailing_commas: TrailingCommas::Multiline,
+            ..defaults
+        }
+    } else if fixture.starts_with("single_quotes") {
+        FormatOptions {
+            quote_style: QuoteStyle::PreferSingle,
+            ..defaults
+        }
+    } else if fixture.starts_with("no_blank_lines") {
+        FormatOptions {
+            blank_lines: BlankLinePolicy::Remove,
+            ..defaults
+        }
+    } else if fixture.starts_with("indent_width_4") {
+        FormatOptions {
+            indent_width: 4,
+            ..defaults
+        }
+    } else if fixture.starts_with("aligned_comments") {
+        FormatOptions {
+            comment_alignment: CommentAlignment::Consecutive,
+            ..defaults
+        }
+    } else {
+        panic!("no options for the {} fixture", fixture)
+    }
+}
+
+// (major, minor) of a version like `3.0` or `2.6.6`
+fn version(s: &str) -> (u32, u32) {
+    let mut parts = s
+        .split('.')
+        .map(|part| part.parse().expect("versions are numbers"));
+    (
+        parts.next().expect("versions have a major part"),
+        parts.next().unwrap_or(0),
+    )
+}
+
+// the fixtures in dir, and in its version directories for the grammar we
+// were built with and older ones
+fn fixtures_in(dir: &Path, fixtures: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).expect("reading the fixtures") {
+        let path = entry.expect("reading the fixtures").path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .expect("fixture names are utf8");
+        if path.is_dir() {
+            if version(name) <= version(rubyfmt::RUBY_VERSION) {
+                fixtures_in(&path, fixtures);
+            }
+        } else if name.ends_with("_actual.rb") {
+            fixtures.push(path);
+        }
+    }
+}
+
+// The VM can only be used from the thread that started it, and each test
+// gets a thread of its own, so every fixture goes through this one test
+#[test]
+fn formats_fixtures_with_their_options() {
+    let mut fixtures = vec![];
+    fixtures_in(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("../fixtures/small/options"),
+        &mut fixtures,
+    );
+    assert!(!fixtures.is_empty(), "found no fixtures");
+
+    let formatter = Formatter::new().expect("starting the ruby VM");
+    for actual_path in fixtures {
+        let name = actual_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .expect("fixture names are utf8")
+            .trim_end_matches("_actual.rb")
+            .to_string();
+        let expected_path = actual_path.with_file_name(format!("{}_expected.rb", name));
+        let actual = fs::read_to_string(&actual_path).expect("reading the fixture");
+        let expected = fs::read_to_string(&expected_path).expect("reading the fixture");
+        let formatter = formatter.with_options(options_for(&name));
+
+        assert_eq!(
+            formatter.format(&actual).expect("formatting the fixture"),
+            expected,
+            "formatting {}",
+            actual_path.display(),
+        );
+        assert_eq!(
+            formatter.format(&expected).expect("formatting the fixture"),
+            expected,
+            "formatting {} again",
+            expected_path.display(),
+        );
+    }
+}
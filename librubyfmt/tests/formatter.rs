@@ -0,0 +1,31 @@
+//! The VM can only be started once per process and used from the thread that
+//! started it, so this is one test, in a test binary of its own
+#![cfg(not(feature = "ruby_extension"))]
+
+use rubyfmt::{Formatter, InitError, RichFormatError};
+use std::thread;
+
+#[test]
+fn formatters_share_the_vm_on_the_thread_that_started_it() {
+    let first = Formatter::new().expect("starting the ruby VM");
+    let second = Formatter::new().expect("the VM is already up on this thread");
+    assert_eq!(first.format("a 1,2,3\n").unwrap(), "a(1, 2, 3)\n");
+    assert_eq!(second.format("a 1,2,3\n").unwrap(), "a(1, 2, 3)\n");
+
+    let from_another_thread = thread::spawn(|| Formatter::new().map(|_| ()))
+        .join()
+        .expect("the thread doesn't panic");
+    assert_eq!(from_another_thread, Err(InitError::WrongThread));
+
+    // the free functions go through a Formatter too
+    let from_another_thread = thread::spawn(|| rubyfmt::format_buffer("a 1,2,3\n"))
+        .join()
+        .expect("the thread doesn't panic");
+    assert!(matches!(
+        from_another_thread,
+        Err(RichFormatError::InitFailed(InitError::WrongThread))
+    ));
+
+    // and the VM is still fine here afterwards
+    assert_eq!(first.format("b 1,2\n").unwrap(), "b(1, 2)\n");
+}
@@ -0,0 +1,46 @@
+//! Everything here goes through FormatterService, so the tests can share its
+//! VM thread whichever threads the harness runs them on
+
+use rubyfmt::{FormatterService, ServiceError};
+use std::thread;
+
+#[test]
+fn formats_for_many_threads_at_once() {
+    let service = FormatterService::new().expect("starting the VM thread");
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            thread::spawn(move || {
+                let output = service
+                    .format(format!("a {},{}\n", i, i + 1))
+                    .wait()
+                    .expect("the service is running");
+                assert_eq!(output.unwrap(), format!("a({}, {})\n", i, i + 1));
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().expect("formatting on the thread works");
+    }
+}
+
+#[test]
+fn services_can_be_created_again() {
+    for _ in 0..2 {
+        // the VM thread is started by the first of these, and outlives it
+        let service = FormatterService::new().expect("starting the VM thread");
+        let output = service.format("a 1\n".to_string()).wait();
+        assert_eq!(output.expect("the service is running").unwrap(), "a(1)\n");
+        let checked = service.check("a(\n".to_string()).wait();
+        assert!(checked.expect("the service is running").is_err());
+    }
+}
+
+#[test]
+fn a_panicking_job_only_fails_itself() {
+    let service = FormatterService::new().expect("starting the VM thread");
+    let panicked = service.run::<(), _>(|_| panic!("boom")).wait();
+    assert_eq!(panicked, Err(ServiceError::Panicked("boom".to_string())));
+
+    let output = service.format("a 1\n".to_string()).wait();
+    assert_eq!(output.expect("the service carries on").unwrap(), "a(1)\n");
+}
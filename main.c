@@ -21,12 +21,13 @@ int main() {
         fprintf(stderr, "failed to init\n");
         exit(1);
     }
-    enum Rubyfmt_FormatError status = RUBYFMT_FORMAT_ERROR_OK;
+    int64_t status = RUBYFMT_FORMAT_ERROR_OK;
     RubyfmtString* out = rubyfmt_format_buffer(buf, bytes_read, &status);
-    if (status != 0) {
+    if (status != RUBYFMT_FORMAT_ERROR_OK) {
+        fprintf(stderr, "%s\n", rubyfmt_last_error_message());
         exit(status);
     }
-    unsigned char* bytes = rubyfmt_string_ptr(out);
+    const unsigned char* bytes = rubyfmt_string_ptr(out);
     size_t len = rubyfmt_string_len(out);
     fwrite(bytes, sizeof(char), len, stdout);
     rubyfmt_string_free(out);
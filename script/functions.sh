@@ -27,6 +27,16 @@ diff_files() {
 }
 
 
+# the major.minor version of the ruby grammar rubyfmt was built with
+built_ruby_version() {
+    f_rubyfmt --ruby-version | grep -o "^[0-9]*\.[0-9]*"
+}
+
+# whether rubyfmt's grammar has the syntax of the passed major.minor version
+built_for_ruby() {
+    [[ $(echo "$1<=$(built_ruby_version)" | bc -l) -ne 0 ]]
+}
+
 test_fixtures_folder() {
     current_dir="$1"
 
@@ -42,13 +52,10 @@ test_fixtures_folder() {
       diff_files /tmp/out.rb "$expected_file"
     done
 
-    ## Recurse over ruby version dirs
-    find "$current_dir" -type d -mindepth 1 -maxdepth 1 -name '*2.*' | while read -r dir
+    ## Recurse over ruby version dirs, for the grammar rubyfmt was built with
+    find "$current_dir" -type d -mindepth 1 -maxdepth 1 -name '[0-9].[0-9]*' | while read -r dir
     do
-        RUBY_VERSION=$(ruby -v | grep -o "[0-9].[0-9]" | head -n 1)
-        base="$(basename "$dir")"
-        fixture_version=${base#"ruby-"}
-        if [[ $(echo "$fixture_version<=$RUBY_VERSION" | bc -l) -ne 0 ]]
+        if built_for_ruby "$(basename "$dir")"
         then
             test_fixtures_folder "$dir"
         fi
@@ -0,0 +1,10 @@
+#!/bin/bash
+set -euxo pipefail
+
+# the checked in header has to match the one the build generates from
+# librubyfmt/src/ffi.rs, `make header` updates it
+(
+cd librubyfmt
+cargo build ${RUBYFMT_FEATURES:+--features "$RUBYFMT_FEATURES"}
+diff include/rubyfmt.h "$(ls -t target/debug/build/rubyfmt-*/out/rubyfmt.h | head -1)"
+)
@@ -1,7 +1,7 @@
 #!/bin/bash
 set -ex
 
-cargo build --release
+cargo build --release ${RUBYFMT_FEATURES:+--features "$RUBYFMT_FEATURES"}
 
 export RUBYFMT_USE_RELEASE=1
 uname -a
@@ -9,7 +9,10 @@ uname -a
 ./script/tests/test_array_literals.sh
 ./script/tests/test_methods.sh
 ./script/tests/test_cli_interface.sh
+./script/tests/test_librubyfmt.sh
 ./script/tests/test_c_main.sh
+./script/tests/test_ruby_extension.sh
 ./script/tests/test_error_handling.sh
+./script/tests/test_ruby_versions.sh
 ./script/tests/test_fixtures.sh
 ./script/tests/test_rspec_stress.sh
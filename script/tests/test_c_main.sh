@@ -11,3 +11,7 @@ then
     echo "methods literals are broken"
     exit 1
 fi
+
+# every function in rubyfmt.h, including the ways they fail
+make target/c_abi_test
+./target/c_abi_test
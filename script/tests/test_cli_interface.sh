@@ -70,7 +70,210 @@ test_i_flag() {
     )
 }
 
+test_run_summary() {
+    (
+    cd "$(mktemp -d)"
+
+    mkdir bees/
+    echo "a 1,2,3" > bees/a_ruby_file_1.rb
+    echo "a(1, 2, 5)" > bees/a_ruby_file_2.rb
+
+    f_rubyfmt -i bees/ 2> summary.txt
+    grep "scanned 2 files" summary.txt
+    grep "reformatted: *1" summary.txt
+    grep "unchanged: *1" summary.txt
+
+    echo "a 1,2,3" > bees/a_ruby_file_1.rb
+    f_rubyfmt --quiet -i bees/ 2> quiet.txt
+    if [ -s quiet.txt ]
+    then
+        echo "--quiet should not print a summary"
+        exit 1
+    fi
+
+    f_rubyfmt --verbose -i bees/ 2> verbose.txt
+    grep "bees/a_ruby_file_1.rb" verbose.txt
+    grep "bees/a_ruby_file_2.rb" verbose.txt
+
+    # directories that happen to match the glob aren't files we skipped
+    mkdir bees/not_a_file.rb
+    f_rubyfmt -i bees/ 2> directory.txt
+    grep "scanned 2 files" directory.txt
+
+    # a file we couldn't format is counted, and fails the run once the
+    # others have been formatted
+    echo "a 1,2,3" > bees/a_ruby_file_1.rb
+    echo "a(1, 2, 3)" > expected_1.rb
+    echo "a(" > bees/a_broken_file.rb
+    if f_rubyfmt -i bees/ 2> broken.txt
+    then
+        echo "a run with a syntax error should exit non-zero"
+        exit 1
+    fi
+    grep "syntax error: *1" broken.txt
+    diff_files bees/a_ruby_file_1.rb expected_1.rb
+    )
+}
+
+test_profile() {
+    (
+    cd "$(mktemp -d)"
+
+    echo "a(1, 2, 3)" > expected.rb
+
+    echo "a 1,2,3" | f_rubyfmt --profile > out.rb 2> profile.txt
+
+    diff_files out.rb expected.rb
+    grep "profile: stdin: parse: .*deserialize: .*format: .*render: " profile.txt
+    )
+}
+
+test_warn_long_lines() {
+    (
+    cd "$(mktemp -d)"
+
+    echo "a = 1" > a_ruby_file.rb
+    echo "b = \"$(printf 'x%.0s' {1..130})\"" >> a_ruby_file.rb
+
+    f_rubyfmt --warn-long-lines a_ruby_file.rb > out.rb 2> warnings.txt
+
+    grep "a_ruby_file.rb:2: line is 136 characters long (max 120), could not break string literal" warnings.txt
+    if grep "a_ruby_file.rb:1:" warnings.txt
+    then
+        echo "short lines should not be reported"
+        exit 1
+    fi
+    )
+}
+
+test_skips_generated_files() {
+    (
+    cd "$(mktemp -d)"
+
+    mkdir bees/
+    printf "# This file is auto-generated\na 1,2,3\n" > bees/schema.rb
+    printf "# Made by our codegen\na 1,2,3\n" > bees/codegen.rb
+    cp bees/schema.rb schema_expected.rb
+    cp bees/codegen.rb codegen_expected.rb
+
+    f_rubyfmt -i --skip-generated --generated-marker="made by our codegen" bees/ 2> summary.txt
+
+    diff_files bees/schema.rb schema_expected.rb
+    diff_files bees/codegen.rb codegen_expected.rb
+    grep "generated: *2" summary.txt
+
+    f_rubyfmt -i --generated-marker="made by our codegen" bees/ 2> summary.txt
+    diff_files bees/codegen.rb codegen_expected.rb
+    grep "generated: *2" summary.txt
+    )
+}
+
+test_formats_generated_files_by_default() {
+    (
+    cd "$(mktemp -d)"
+
+    mkdir bees/
+    printf "# This file is auto-generated\na 1,2,3\n" > bees/schema.rb
+    printf "# This file is auto-generated\na(1, 2, 3)\n" > schema_expected.rb
+
+    f_rubyfmt -i bees/ 2> summary.txt
+
+    diff_files bees/schema.rb schema_expected.rb
+    grep "generated: *0" summary.txt
+    grep "reformatted: *1" summary.txt
+    )
+}
+
+test_preserves_source_encoding() {
+    (
+    cd "$(mktemp -d)"
+
+    # "caf\xe9" is "café" in ISO-8859-1, and isn't valid UTF-8
+    printf "# encoding: iso-8859-1\na \"caf\xe9\",2\n" > latin1.rb
+    printf "# encoding: iso-8859-1\na(\"caf\xe9\", 2)\n" > expected.rb
+
+    f_rubyfmt latin1.rb > out.rb
+    diff_files out.rb expected.rb
+
+    f_rubyfmt -i latin1.rb
+    diff_files latin1.rb expected.rb
+
+    printf "a \"caf\xe9\"\n" > invalid.rb
+    if f_rubyfmt invalid.rb; then
+        echo "expected invalid UTF-8 to be rejected"
+        exit 1
+    fi
+    )
+}
+
+test_preserves_line_endings_and_bom() {
+    (
+    cd "$(mktemp -d)"
+
+    printf "a 1,2\r\nb 3\r\n" > crlf.rb
+    printf "a(1, 2)\r\nb(3)\r\n" > crlf_expected.rb
+    printf "\xef\xbb\xbfa 1,2\n" > bom.rb
+    printf "\xef\xbb\xbfa(1, 2)\n" > bom_expected.rb
+
+    f_rubyfmt -i crlf.rb bom.rb
+
+    diff_files crlf.rb crlf_expected.rb
+    diff_files bom.rb bom_expected.rb
+
+    # the string literal's lone LF and the heredoc's CRLFs are source, not
+    # line breaks rubyfmt writes, so they're kept as they are
+    printf "a \"x\ny\"\r\nb = <<EOS\r\nz\r\nEOS\r\nc 1,2\r\n" > mixed.rb
+    printf "a(\"x\ny\")\r\nb = <<EOS\r\nz\r\nEOS\r\nc(1, 2)\r\n" > mixed_expected.rb
+    f_rubyfmt -i mixed.rb
+    diff_files mixed.rb mixed_expected.rb
+
+    printf "a 1,2\r\nb 3\r\n" > forced_lf.rb
+    printf "a(1, 2)\nb(3)\n" > forced_lf_expected.rb
+    f_rubyfmt -i --line-endings=lf forced_lf.rb
+    diff_files forced_lf.rb forced_lf_expected.rb
+
+    printf "a 1,2\n" > forced_crlf.rb
+    printf "a(1, 2)\r\n" > forced_crlf_expected.rb
+    f_rubyfmt --line-endings=crlf forced_crlf.rb > forced_crlf_out.rb
+    diff_files forced_crlf_out.rb forced_crlf_expected.rb
+    )
+}
+
+test_ruby_version() {
+    (
+    cd "$(mktemp -d)"
+
+    version=$(f_rubyfmt --ruby-version)
+    if ! echo "$version" | grep -qE '^[0-9]+\.[0-9]+\.[0-9]+$'
+    then
+        echo "expected --ruby-version to print a ruby version, got $version"
+        exit 1
+    fi
+
+    # pattern matching is new in 2.7, so older grammars must reject it
+    echo "case 1; in Integer; end" > pattern_matching.rb
+    if [[ $(echo "${version%.*}<2.7" | bc -l) -ne 0 ]]
+    then
+        if f_rubyfmt --syntax-check pattern_matching.rb > /dev/null 2>&1
+        then
+            echo "expected ruby $version's grammar to reject pattern matching"
+            exit 1
+        fi
+    else
+        f_rubyfmt --syntax-check pattern_matching.rb
+    fi
+    )
+}
+
 test_single_file_stdout
 test_stdin_stdout
 test_dir_no_i_flag
 test_i_flag
+test_run_summary
+test_profile
+test_warn_long_lines
+test_skips_generated_files
+test_formats_generated_files_by_default
+test_preserves_source_encoding
+test_preserves_line_endings_and_bom
+test_ruby_version
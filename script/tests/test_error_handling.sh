@@ -2,7 +2,7 @@
 set -euxo pipefail
 
 source "./script/functions.sh"
-cargo build --release
+cargo build --release ${RUBYFMT_FEATURES:+--features "$RUBYFMT_FEATURES"}
 
 test_stdin_stdout() {
     (
@@ -28,5 +28,26 @@ test_single_file_stdout() {
     )
 }
 
+test_syntax_check() {
+    (
+    cd "$(mktemp -d)"
+
+    echo "a 1,2,3" > good.rb
+    echo "a 1,2," > bad.rb
+
+    f_rubyfmt --syntax-check good.rb > out
+    grep "Syntax OK" out
+
+    if f_rubyfmt --syntax-check good.rb bad.rb 2> errors
+    then
+        echo "rubyfmt --syntax-check didn't fail as expected"
+        exit 1
+    fi
+    grep "^bad.rb:[0-9]*:[0-9]*: syntax error" errors
+    diff_files good.rb <(echo "a 1,2,3")
+    )
+}
+
 test_stdin_stdout
 test_single_file_stdout
+test_syntax_check
@@ -0,0 +1,7 @@
+#!/bin/bash
+set -ex
+
+# librubyfmt's own tests, which start the embedded ruby VM. test_hooks lets
+# them make rubyfmt panic
+cd librubyfmt
+cargo test --release --features test_hooks ${RUBYFMT_FEATURES:+--features "$RUBYFMT_FEATURES"}
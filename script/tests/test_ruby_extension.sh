@@ -0,0 +1,30 @@
+#!/bin/bash
+set -ex
+
+make ruby_extension
+
+ruby -I target -e '
+require "rubyfmt"
+
+raise "format is broken" unless Rubyfmt.format("a 1,2,3\n") == "a(1, 2, 3)\n"
+raise "formatted? is broken" unless Rubyfmt.formatted?("a(1, 2, 3)\n")
+raise "formatted? is broken" if Rubyfmt.formatted?("a 1,2,3\n")
+raise "options are broken" unless Rubyfmt.format(%(a "b"\n), quote_style: :prefer_single) == "a(\x27b\x27)\n"
+raise "comment alignment is broken" unless Rubyfmt.format("a = 1 # one\nbcd = 2 # two\n", comment_alignment: :consecutive) == "a = 1   # one\nbcd = 2 # two\n"
+
+begin
+  Rubyfmt.format("def foo(\n")
+  raise "expected a syntax error"
+rescue Rubyfmt::SyntaxError
+end
+
+# collect on every allocation, so anything we hold on to from rust without
+# the GC knowing about it gets freed out from under us
+GC.stress = true
+20.times do
+  raise "format is broken under GC.stress" unless Rubyfmt.format("a 1,2,3\n", line_width: 80, quote_style: :double) == "a(1, 2, 3)\n"
+  raise "formatted? is broken under GC.stress" unless Rubyfmt.formatted?("a(1, 2, 3)\n", trailing_commas: :never)
+end
+GC.stress = false
+raise "the GC was left disabled" if GC.enable
+'
@@ -0,0 +1,40 @@
+#!/bin/bash
+set -euxo pipefail
+
+source "./script/functions.sh"
+cargo build --release ${RUBYFMT_FEATURES:+--features "$RUBYFMT_FEATURES"}
+
+# --ruby-version reports the grammar RUBYFMT_FEATURES picked, 2.6 without one
+test_ruby_version() {
+    expected="2.6"
+    if [[ "${RUBYFMT_FEATURES:-}" =~ ruby_([0-9]+)_([0-9]+) ]]
+    then
+        expected="${BASH_REMATCH[1]}.${BASH_REMATCH[2]}"
+    fi
+    f_rubyfmt --ruby-version | grep -x "${expected//./\\.}\.[0-9]*"
+}
+
+# each file in ci/syntax_since/<version> only parses from that ruby on, so
+# older grammars have to reject it as a syntax error, just as that ruby would
+test_syntax_since() {
+    errors="$(mktemp)"
+    find ci/syntax_since -type d -mindepth 1 -maxdepth 1 | while read -r dir
+    do
+        for file in "$dir"/*.rb
+        do
+            if built_for_ruby "$(basename "$dir")"
+            then
+                f_rubyfmt --syntax-check "$file"
+            elif f_rubyfmt --syntax-check "$file" 2> "$errors"
+            then
+                echo "ruby $(built_ruby_version) doesn't have the syntax in $file, but rubyfmt accepted it"
+                exit 1
+            else
+                grep "^$file:[0-9]*:[0-9]*: " "$errors"
+            fi
+        done
+    done
+}
+
+test_ruby_version
+test_syntax_since
@@ -3,17 +3,20 @@ extern crate glob;
 extern crate libc;
 extern crate rubyfmt;
 
-use std::fs::{metadata, read_to_string, OpenOptions};
+use std::cmp::Reverse;
+use std::fs::{metadata, read, OpenOptions};
 use std::io::{self, Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::{Duration, Instant};
 
 use glob::glob;
 
 #[derive(Debug)]
 enum FileError {
     IO(io::Error),
-    SyntaxError,
+    SyntaxError(Vec<rubyfmt::SyntaxErrorDetail>),
+    FormatFailure,
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -22,52 +25,407 @@ enum ErrorExit {
     Exit,
 }
 
-fn rubyfmt_file(file_path: PathBuf) -> Result<(), FileError> {
-    let buffer = read_to_string(file_path.clone()).map_err(FileError::IO)?;
-    let res = rubyfmt::format_buffer(&buffer);
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum FileOutcome {
+    Reformatted,
+    Unchanged,
+    Generated,
+    SyntaxError,
+    InternalError,
+}
+
+impl FileOutcome {
+    fn label(self) -> &'static str {
+        match self {
+            FileOutcome::Reformatted => "reformatted",
+            FileOutcome::Unchanged => "unchanged",
+            FileOutcome::Generated => "skipped (generated)",
+            FileOutcome::SyntaxError => "syntax error",
+            FileOutcome::InternalError => "internal error",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+struct CliFlags {
+    verbosity: Verbosity,
+    profile: bool,
+    warn_long_lines: bool,
+    syntax_check: bool,
+    line_endings: rubyfmt::LineEndings,
+}
+
+const SLOWEST_FILES_TO_REPORT: usize = 5;
+
+struct RunSummary {
+    flags: CliFlags,
+    generated_file_markers: Option<rubyfmt::GeneratedFileMarkers>,
+    started_at: Instant,
+    files: Vec<(PathBuf, FileOutcome, Duration)>,
+    stats: rubyfmt::FormatStats,
+}
+
+impl RunSummary {
+    fn new(flags: CliFlags, generated_file_markers: Option<rubyfmt::GeneratedFileMarkers>) -> Self {
+        RunSummary {
+            flags,
+            generated_file_markers,
+            started_at: Instant::now(),
+            files: vec![],
+            stats: rubyfmt::FormatStats::default(),
+        }
+    }
+
+    fn record(&mut self, file_path: PathBuf, outcome: FileOutcome, elapsed: Duration) {
+        if self.flags.verbosity == Verbosity::Verbose {
+            eprintln!(
+                "{}: {} ({:.2?})",
+                outcome.label(),
+                file_path.display(),
+                elapsed
+            );
+        }
+        self.files.push((file_path, outcome, elapsed));
+    }
+
+    fn record_report(&mut self, file_path: &Path, report: &rubyfmt::FormatReport) {
+        let source = file_path.display().to_string();
+        if self.flags.profile {
+            print_profile(&source, report.stats);
+        }
+        if self.flags.warn_long_lines {
+            print_long_lines(&source, &report.long_lines);
+        }
+        self.stats += report.stats;
+    }
+
+    fn count(&self, outcome: FileOutcome) -> usize {
+        self.files.iter().filter(|(_, o, _)| *o == outcome).count()
+    }
+
+    fn has_failures(&self) -> bool {
+        self.count(FileOutcome::SyntaxError) + self.count(FileOutcome::InternalError) > 0
+    }
+
+    fn print(&self) {
+        if self.flags.profile {
+            print_profile("all files", self.stats);
+        }
+        if self.flags.verbosity == Verbosity::Quiet {
+            return;
+        }
+
+        eprintln!(
+            "rubyfmt: scanned {} files in {:.2?}",
+            self.files.len(),
+            self.started_at.elapsed()
+        );
+        for outcome in &[
+            FileOutcome::Reformatted,
+            FileOutcome::Unchanged,
+            FileOutcome::Generated,
+            FileOutcome::SyntaxError,
+            FileOutcome::InternalError,
+        ] {
+            eprintln!(
+                "  {:<21} {}",
+                format!("{}:", outcome.label()),
+                self.count(*outcome)
+            );
+        }
+
+        let mut slowest = self
+            .files
+            .iter()
+            .filter(|(_, o, _)| *o != FileOutcome::Generated)
+            .collect::<Vec<_>>();
+        slowest.sort_by_key(|(_, _, elapsed)| Reverse(*elapsed));
+        if !slowest.is_empty() {
+            eprintln!("slowest files:");
+            for (file_path, _, elapsed) in slowest.into_iter().take(SLOWEST_FILES_TO_REPORT) {
+                eprintln!("  {:>10.2?} {}", elapsed, file_path.display());
+            }
+        }
+    }
+}
+
+fn format_options(flags: CliFlags) -> rubyfmt::FormatOptions {
+    rubyfmt::FormatOptions {
+        line_endings: flags.line_endings,
+        ..rubyfmt::FormatOptions::default()
+    }
+}
+
+fn print_profile(source: &str, stats: rubyfmt::FormatStats) {
+    eprintln!("profile: {}: {}", source, stats);
+}
+
+fn print_long_lines(source: &str, long_lines: &[rubyfmt::LongLine]) {
+    for long_line in long_lines {
+        eprintln!(
+            "warning: {}:{}: line is {} characters long (max {}), could not break {}",
+            source, long_line.line_number, long_line.width, long_line.max_width, long_line.culprit,
+        );
+    }
+}
+
+fn rubyfmt_file(file_path: PathBuf, summary: &mut RunSummary) -> Result<FileOutcome, FileError> {
+    let bytes = read(file_path.clone()).map_err(FileError::IO)?;
+    let options = format_options(summary.flags);
+    let res = rubyfmt::SourceEncoding::decode(&bytes).and_then(|(buffer, encoding)| {
+        let report = match &summary.generated_file_markers {
+            Some(markers) => rubyfmt::format_buffer_unless_generated(&buffer, &options, markers)?,
+            None => Some(rubyfmt::format_buffer_with_report(&buffer, &options)?),
+        };
+        Ok(report.map(|report| (report, encoding)))
+    });
     match res {
-        Ok(res) => {
+        Ok(None) => Ok(FileOutcome::Generated),
+        Ok(Some((report, encoding))) => {
+            summary.record_report(&file_path, &report);
+            let res = encoding.encode(report.output).map_err(|e| {
+                handle_error_from(e, &format!("{}", file_path.display()), ErrorExit::NoExit);
+                FileError::FormatFailure
+            })?;
+            if res == bytes {
+                return Ok(FileOutcome::Unchanged);
+            }
             let mut file = OpenOptions::new()
                 .write(true)
                 .truncate(true)
                 .open(file_path)
-                .expect("file");
-            write!(file, "{}", res).map_err(FileError::IO)?;
-            Ok(())
+                .map_err(FileError::IO)?;
+            file.write_all(&res).map_err(FileError::IO)?;
+            Ok(FileOutcome::Reformatted)
         }
-        Err(rubyfmt::RichFormatError::SyntaxError) => Err(FileError::SyntaxError),
+        Err(rubyfmt::RichFormatError::SyntaxError(errors)) => Err(FileError::SyntaxError(errors)),
         Err(e) => {
-            // we're in a formatting loop, so print, and OK
+            // we're in a formatting loop, so print, and carry on
             handle_error_from(e, &format!("{}", file_path.display()), ErrorExit::NoExit);
-            Ok(())
+            Err(FileError::FormatFailure)
         }
     }
 }
 
-fn rubyfmt_dir(path: &str) -> io::Result<()> {
-    for entry in glob(&format!("{}/**/*.rb", path)).expect("it exists") {
-        let p = entry.expect("should not be null");
-        let res = rubyfmt_file(p.clone());
-        if let Err(FileError::SyntaxError) = res {
+fn format_and_record(file_path: PathBuf, summary: &mut RunSummary) {
+    let started_at = Instant::now();
+    let outcome = match rubyfmt_file(file_path.clone(), summary) {
+        Ok(outcome) => outcome,
+        Err(FileError::SyntaxError(errors)) => {
             eprintln!(
                 "warning: {} contains syntax errors, ignoring for now",
-                p.display()
+                file_path.display()
             );
+            print_syntax_errors(&file_path.display().to_string(), &errors);
+            FileOutcome::SyntaxError
+        }
+        Err(FileError::IO(e)) => {
+            eprintln!("error: could not format {}: {}", file_path.display(), e);
+            FileOutcome::InternalError
+        }
+        Err(FileError::FormatFailure) => FileOutcome::InternalError,
+    };
+    summary.record(file_path, outcome, started_at.elapsed());
+}
+
+fn rubyfmt_dir(path: &str, summary: &mut RunSummary) -> io::Result<()> {
+    for entry in glob(&format!("{}/**/*.rb", path)).expect("it exists") {
+        let p = entry.expect("should not be null");
+        // the glob also matches directories with names like `foo.rb`
+        if p.is_file() {
+            format_and_record(p, summary);
         }
     }
     Ok(())
 }
 
-fn format_parts(parts: &[String]) {
+fn format_parts(
+    parts: &[String],
+    flags: CliFlags,
+    generated_file_markers: Option<rubyfmt::GeneratedFileMarkers>,
+) {
+    let mut summary = RunSummary::new(flags, generated_file_markers);
     for part in parts {
         if let Ok(md) = metadata(part) {
             if md.is_dir() {
-                rubyfmt_dir(part).expect("failed to format dir");
+                rubyfmt_dir(part, &mut summary).expect("failed to format dir");
             } else if md.is_file() {
-                rubyfmt_file(part.into()).expect("failed to format file");
+                format_and_record(part.into(), &mut summary);
             }
         }
     }
+    summary.print();
+    if summary.has_failures() {
+        exit(1);
+    }
+}
+
+fn print_syntax_errors(source: &str, errors: &[rubyfmt::SyntaxErrorDetail]) {
+    for error in errors {
+        eprintln!("{}:{}", source, error);
+    }
+}
+
+// check every file and directory in parts like `ruby -c`, without formatting
+// anything, returning false if any of them contained invalid syntax
+fn syntax_check_parts(parts: &[String]) -> bool {
+    let mut file_paths = vec![];
+    for part in parts {
+        match metadata(part) {
+            Ok(md) if md.is_dir() => {
+                for entry in glob(&format!("{}/**/*.rb", part)).expect("it exists") {
+                    let p = entry.expect("should not be null");
+                    if p.is_file() {
+                        file_paths.push(p);
+                    }
+                }
+            }
+            Ok(_) => file_paths.push(part.into()),
+            Err(_) => {
+                eprintln!("{} does not exist", part);
+                return false;
+            }
+        }
+    }
+
+    let mut all_ok = true;
+    for file_path in file_paths {
+        let source = file_path.display().to_string();
+        let ok = match read(&file_path) {
+            Ok(bytes) => syntax_check_bytes(&bytes, &source),
+            Err(e) => {
+                eprintln!("error: could not read {}: {}", source, e);
+                false
+            }
+        };
+        all_ok &= ok;
+    }
+    all_ok
+}
+
+fn syntax_check_bytes(bytes: &[u8], source: &str) -> bool {
+    let res = rubyfmt::SourceEncoding::decode(bytes)
+        .and_then(|(buffer, _)| rubyfmt::syntax_check(&buffer));
+    match res {
+        Ok(()) => true,
+        Err(rubyfmt::RichFormatError::SyntaxError(errors)) => {
+            print_syntax_errors(source, &errors);
+            false
+        }
+        Err(e) => {
+            handle_error_from(e, source, ErrorExit::NoExit);
+            false
+        }
+    }
+}
+
+// pull the `--quiet`, `--verbose`, `--profile`, `--warn-long-lines`,
+// `--syntax-check` and `--line-endings` flags out of the argument list, so
+// the positional handling in main only ever sees files and directories
+fn take_cli_flags(args: &mut Vec<String>) -> CliFlags {
+    let mut flags = CliFlags {
+        verbosity: Verbosity::Normal,
+        profile: false,
+        warn_long_lines: false,
+        syntax_check: false,
+        line_endings: rubyfmt::LineEndings::Preserve,
+    };
+    args.retain(|arg| match arg.as_str() {
+        line_endings if line_endings.starts_with("--line-endings=") => {
+            flags.line_endings = match &line_endings["--line-endings=".len()..] {
+                "preserve" => rubyfmt::LineEndings::Preserve,
+                "lf" => rubyfmt::LineEndings::Lf,
+                "crlf" => rubyfmt::LineEndings::Crlf,
+                other => {
+                    eprintln!("--line-endings expects preserve, lf or crlf, got {}", other);
+                    exit(1)
+                }
+            };
+            false
+        }
+        "--quiet" => {
+            flags.verbosity = Verbosity::Quiet;
+            false
+        }
+        "--verbose" => {
+            flags.verbosity = Verbosity::Verbose;
+            false
+        }
+        "--profile" => {
+            flags.profile = true;
+            false
+        }
+        "--warn-long-lines" => {
+            flags.warn_long_lines = true;
+            false
+        }
+        "--syntax-check" => {
+            flags.syntax_check = true;
+            false
+        }
+        _ => true,
+    });
+    flags
+}
+
+// pull the flags configuring how we spot generated files out of the argument
+// list. In place runs only skip generated files when asked to, with
+// `--skip-generated` or by passing a `--generated-marker`
+fn take_generated_file_markers(args: &mut Vec<String>) -> Option<rubyfmt::GeneratedFileMarkers> {
+    let mut markers = rubyfmt::GeneratedFileMarkers::default();
+    let mut skip_generated = false;
+    args.retain(|arg| {
+        if arg == "--skip-generated" {
+            skip_generated = true;
+            false
+        } else if let Some(marker) = arg.strip_prefix("--generated-marker=") {
+            markers.markers.push(marker.to_string());
+            skip_generated = true;
+            false
+        } else if let Some(lines) = arg.strip_prefix("--generated-marker-lines=") {
+            markers.max_comment_lines = lines.parse().unwrap_or_else(|_| {
+                eprintln!("--generated-marker-lines expects a number, got {}", lines);
+                exit(1)
+            });
+            false
+        } else {
+            true
+        }
+    });
+
+    if skip_generated {
+        Some(markers)
+    } else {
+        None
+    }
+}
+
+fn format_bytes_to_stdout(bytes: &[u8], source: &str, flags: CliFlags) {
+    let res = rubyfmt::SourceEncoding::decode(bytes).and_then(|(buffer, encoding)| {
+        let report = rubyfmt::format_buffer_with_report(&buffer, &format_options(flags))?;
+        let output = encoding.encode(report.output.clone())?;
+        Ok((report, output))
+    });
+    match res {
+        Ok((report, output)) => {
+            io::stdout().write_all(&output).expect("write works");
+            io::stdout().flush().expect("flush works");
+            if flags.profile {
+                print_profile(source, report.stats);
+            }
+            if flags.warn_long_lines {
+                print_long_lines(source, &report.long_lines);
+            }
+        }
+        Err(e) => handle_error_from(e, source, ErrorExit::Exit),
+    }
 }
 
 fn handle_error_from(err: rubyfmt::RichFormatError, source: &str, error_exit: ErrorExit) {
@@ -78,8 +436,9 @@ fn handle_error_from(err: rubyfmt::RichFormatError, source: &str, error_exit: Er
         }
     };
     match err {
-        SyntaxError => {
+        SyntaxError(errors) => {
             eprintln!("{} contained invalid ruby syntax", source);
+            print_syntax_errors(source, &errors);
             e();
         }
         rubyfmt::RichFormatError::RipperParseFailure(_) => {
@@ -103,6 +462,21 @@ fn handle_error_from(err: rubyfmt::RichFormatError, source: &str, error_exit: Er
             eprintln!("IO error occured while running rubyfmt: {:?}, this may indicate a programming error, please file a bug report at https://github.com/penelopezone/rubyfmt/issues/new", ioe);
             e();
         }
+        InvalidUtf8(utf8_error) => {
+            eprintln!(
+                "{} is not valid UTF-8: {}, add a magic comment if it's in another encoding",
+                source, utf8_error
+            );
+            e();
+        }
+        InvalidEncoding(s) => {
+            eprintln!("{} could not be transcoded: {}", source, s);
+            e();
+        }
+        InitFailed(init_error) => {
+            eprintln!("rubyfmt could not start its ruby VM: {:?}", init_error);
+            exit(1);
+        }
         rubyfmt::RichFormatError::OtherRubyError(s) => {
             eprintln!("A ruby error occured: {}, please file a bug report at https://github.com/penelopezone/rubyfmt/issues/new", s);
             exit(1);
@@ -115,41 +489,52 @@ fn main() {
     if res != rubyfmt::InitStatus::OK as libc::c_int {
         panic!("bad init status");
     }
-    let args: Vec<String> = std::env::args().collect();
+    let mut args: Vec<String> = std::env::args().collect();
+    let flags = take_cli_flags(&mut args);
+    let generated_file_markers = take_generated_file_markers(&mut args);
     if args.len() >= 2 && (args[1] == "--help" || args[1] == "-h") {
         eprintln!("{}", include_str!("../README.md"));
         exit(1);
     }
 
+    if args.len() >= 2 && args[1] == "--ruby-version" {
+        println!("{}", rubyfmt::RUBY_VERSION);
+        return;
+    }
+
+    if flags.syntax_check {
+        let ok = if args.len() == 1 {
+            // check stdin
+            let mut bytes = vec![];
+            io::stdin()
+                .read_to_end(&mut bytes)
+                .expect("reading from stdin to not fail");
+            syntax_check_bytes(&bytes, "stdin")
+        } else {
+            syntax_check_parts(&args[1..args.len()])
+        };
+        if !ok {
+            exit(1);
+        }
+        println!("Syntax OK");
+        return;
+    }
+
     if args.len() == 1 {
         // consume stdin
-        let mut buffer = String::new();
+        let mut bytes = vec![];
         io::stdin()
-            .read_to_string(&mut buffer)
+            .read_to_end(&mut bytes)
             .expect("reading frmo stdin to not fail");
-        let res = rubyfmt::format_buffer(&buffer);
-        match res {
-            Ok(res) => {
-                write!(io::stdout(), "{}", res).expect("write works");
-                io::stdout().flush().expect("flush works");
-            }
-            Err(e) => handle_error_from(e, "stdin", ErrorExit::Exit),
-        }
+        format_bytes_to_stdout(&bytes, "stdin", flags);
     } else if args.len() == 2 {
         // consume a filename
         if let Ok(md) = metadata(args[1].clone()) {
             if md.is_dir() {
-                format_parts(&[args[1].clone()])
+                format_parts(&[args[1].clone()], flags, generated_file_markers)
             } else {
-                let buffer = read_to_string(args[1].clone()).expect("file exists");
-                let res = rubyfmt::format_buffer(&buffer);
-                match res {
-                    Ok(res) => {
-                        write!(io::stdout(), "{}", res).expect("write works");
-                        io::stdout().flush().expect("flush works");
-                    }
-                    Err(e) => handle_error_from(e, &args[1], ErrorExit::Exit),
-                }
+                let bytes = read(args[1].clone()).expect("file exists");
+                format_bytes_to_stdout(&bytes, &args[1], flags);
             }
         } else {
             eprintln!("{} does not exist", args[1]);
@@ -158,10 +543,10 @@ fn main() {
     } else if args[1] == "-i" {
         // inline a file or directory
         let parts = &args[2..args.len()];
-        format_parts(parts);
+        format_parts(parts, flags, generated_file_markers);
     } else {
         // inline many files and directories
         let parts = &args[1..args.len()];
-        format_parts(parts);
+        format_parts(parts, flags, generated_file_markers);
     }
 }